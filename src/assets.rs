@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use walkdir::WalkDir;
+
+/// How many files a copy wrote and how many it left alone because they were
+/// already up to date.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CopyStats {
+    pub copied: usize,
+    pub skipped: usize,
+}
+
+impl CopyStats {
+    fn add(&mut self, copied: bool) {
+        if copied {
+            self.copied += 1;
+        } else {
+            self.skipped += 1;
+        }
+    }
+}
+
+/// Copies everything under `source_dir` into `dest_dir`, keeping relative paths.
+/// A missing source directory just means there is nothing to copy.
+pub fn copy_dir(source_dir: &Path, dest_dir: &Path) -> Result<CopyStats> {
+    let mut stats = CopyStats::default();
+    if !source_dir.is_dir() {
+        return Ok(stats);
+    }
+
+    for entry in WalkDir::new(source_dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("failed to read {}", source_dir.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(source_dir)?;
+        stats.add(copy_file_if_changed(entry.path(), &dest_dir.join(relative))?);
+    }
+
+    info!(
+        "Copied {} files from {} ({} unchanged)",
+        stats.copied,
+        source_dir.display(),
+        stats.skipped
+    );
+    Ok(stats)
+}
+
+/// Copies the non-Markdown files found in the content directory.
+///
+/// `bundles` maps the directory of a page bundle (a directory with an `index.md`)
+/// to the directory that page is written to, so images and downloads sitting next
+/// to a page follow it even when a permalink moves the page somewhere else. Other
+/// files keep their path relative to the content directory.
+pub fn copy_page_resources(
+    resources: &[PathBuf],
+    content_dir: &Path,
+    output_dir: &Path,
+    bundles: &HashMap<PathBuf, PathBuf>,
+) -> Result<CopyStats> {
+    let mut stats = CopyStats::default();
+    for resource in resources {
+        let dest = resource_destination(resource, content_dir, output_dir, bundles);
+        stats.add(copy_file_if_changed(resource, &dest)?);
+    }
+
+    if !resources.is_empty() {
+        info!("Copied {} page resources ({} unchanged)", stats.copied, stats.skipped);
+    }
+    Ok(stats)
+}
+
+fn resource_destination(
+    resource: &Path,
+    content_dir: &Path,
+    output_dir: &Path,
+    bundles: &HashMap<PathBuf, PathBuf>,
+) -> PathBuf {
+    // The closest enclosing bundle wins.
+    for bundle_dir in resource.ancestors().skip(1) {
+        if let Some(page_dir) = bundles.get(bundle_dir) {
+            let relative = resource.strip_prefix(bundle_dir).unwrap_or(resource);
+            return page_dir.join(relative);
+        }
+        if bundle_dir == content_dir {
+            break;
+        }
+    }
+
+    let relative = resource
+        .strip_prefix(content_dir)
+        .ok()
+        .or_else(|| resource.file_name().map(Path::new))
+        .unwrap_or(resource);
+    output_dir.join(relative)
+}
+
+/// Copies `source` to `dest` unless `dest` already has the same size and
+/// modification time. Returns whether the file was copied.
+pub fn copy_file_if_changed(source: &Path, dest: &Path) -> Result<bool> {
+    let source_meta = fs::metadata(source)
+        .with_context(|| format!("failed to read {}", source.display()))?;
+
+    if let Ok(dest_meta) = fs::metadata(dest) {
+        let same_size = dest_meta.len() == source_meta.len();
+        let same_time = match (dest_meta.modified(), source_meta.modified()) {
+            (Ok(dest_time), Ok(source_time)) => dest_time == source_time,
+            _ => false,
+        };
+        if same_size && same_time {
+            debug!("Unchanged: {}", dest.display());
+            return Ok(false);
+        }
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    fs::copy(source, dest)
+        .with_context(|| format!("failed to copy {} to {}", source.display(), dest.display()))?;
+
+    // Give the copy the same modification time as the source so the next build
+    // can tell it is up to date.
+    if let Ok(modified) = source_meta.modified() {
+        fs::File::options()
+            .write(true)
+            .open(dest)
+            .and_then(|file| file.set_modified(modified))
+            .with_context(|| format!("failed to set the modification time of {}", dest.display()))?;
+    }
+
+    debug!("Copied {} to {}", source.display(), dest.display());
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_dir_keeps_relative_paths() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("static");
+        fs::create_dir_all(source.join("css")).unwrap();
+        fs::write(source.join("css/site.css"), "body {}").unwrap();
+        fs::write(source.join("robots.txt"), "User-agent: *").unwrap();
+
+        let output = dir.path().join("output");
+        let stats = copy_dir(&source, &output).unwrap();
+
+        assert_eq!(stats, CopyStats { copied: 2, skipped: 0 });
+        assert_eq!(fs::read_to_string(output.join("css/site.css")).unwrap(), "body {}");
+        assert_eq!(fs::read_to_string(output.join("robots.txt")).unwrap(), "User-agent: *");
+    }
+
+    #[test]
+    fn test_copy_dir_skips_unchanged_files() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("static");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("a.txt"), "a").unwrap();
+        fs::write(source.join("b.txt"), "b").unwrap();
+        let output = dir.path().join("output");
+
+        copy_dir(&source, &output).unwrap();
+        fs::write(source.join("b.txt"), "bb").unwrap();
+        let stats = copy_dir(&source, &output).unwrap();
+
+        assert_eq!(stats, CopyStats { copied: 1, skipped: 1 });
+        assert_eq!(fs::read_to_string(output.join("b.txt")).unwrap(), "bb");
+    }
+
+    #[test]
+    fn test_copy_dir_missing_source() {
+        let dir = tempdir().unwrap();
+        let stats = copy_dir(&dir.path().join("static"), &dir.path().join("output")).unwrap();
+
+        assert_eq!(stats, CopyStats::default());
+    }
+
+    #[test]
+    fn test_resource_destination() {
+        let content = Path::new("content");
+        let output = Path::new("output");
+        let bundles = HashMap::from([(
+            PathBuf::from("content/blog/trip"),
+            PathBuf::from("output/blog/2024/trip"),
+        )]);
+
+        assert_eq!(
+            resource_destination(Path::new("content/blog/trip/photos/a.jpg"), content, output, &bundles),
+            PathBuf::from("output/blog/2024/trip/photos/a.jpg")
+        );
+        assert_eq!(
+            resource_destination(Path::new("content/docs/diagram.png"), content, output, &bundles),
+            PathBuf::from("output/docs/diagram.png")
+        );
+    }
+}
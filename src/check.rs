@@ -0,0 +1,436 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result, bail};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::serve::percent_decode;
+
+/// What checking the links in a built site found.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Report {
+    /// How many HTML files were checked.
+    pub checked: usize,
+    /// The pages with broken or external links, in path order.
+    pub pages: Vec<PageReport>,
+}
+
+/// The links on one page that are broken or go to other sites.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PageReport {
+    /// The page's file, relative to the output directory.
+    pub page: String,
+    pub broken: Vec<BrokenLink>,
+    /// Links to other sites. They are listed, but not fetched.
+    pub external: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BrokenLink {
+    pub url: String,
+    pub reason: String,
+}
+
+impl Report {
+    pub fn broken_count(&self) -> usize {
+        self.pages.iter().map(|page| page.broken.len()).sum()
+    }
+
+    /// Every external URL on the site, once each.
+    pub fn external_urls(&self) -> BTreeSet<&str> {
+        self.pages.iter().flat_map(|page| page.external.iter().map(String::as_str)).collect()
+    }
+
+    /// The report as text for a terminal: the broken links under the page they
+    /// are on, then the external URLs, then a summary.
+    pub fn to_human(&self) -> String {
+        let mut text = String::new();
+        for page in self.pages.iter().filter(|page| !page.broken.is_empty()) {
+            text.push_str(&format!("{}\n", page.page));
+            for link in &page.broken {
+                text.push_str(&format!("  `{}`: {}\n", link.url, link.reason));
+            }
+        }
+
+        let external = self.external_urls();
+        if !external.is_empty() {
+            text.push_str("External URLs (not checked):\n");
+            for url in &external {
+                text.push_str(&format!("  {}\n", url));
+            }
+        }
+
+        let broken_pages = self.pages.iter().filter(|page| !page.broken.is_empty()).count();
+        text.push_str(&format!(
+            "Checked {} pages: {} broken links on {} pages, {} external URLs\n",
+            self.checked,
+            self.broken_count(),
+            broken_pages,
+            external.len()
+        ));
+        text
+    }
+}
+
+/// Checks every `href` and `src` in the HTML files under `output_dir`: links
+/// within the site have to point at a file that exists, and a `#fragment` at
+/// an element with that id on the page linked to. Absolute URLs starting with
+/// `base_url` count as links within the site.
+pub fn check_output(output_dir: &Path, base_url: &str) -> Result<Report> {
+    if !output_dir.is_dir() {
+        bail!("there is no output directory {}, build the site first", output_dir.display());
+    }
+
+    // Every page is read up front, since links can point at ids on any of them.
+    let mut pages = HashMap::new();
+    for entry in WalkDir::new(output_dir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() && is_html(entry.path()) {
+            let html = fs::read_to_string(entry.path())
+                .with_context(|| format!("failed to read {}", entry.path().display()))?;
+            pages.insert(normalize(entry.path()), scan(&html));
+        }
+    }
+
+    let mut paths: Vec<&PathBuf> = pages.keys().collect();
+    paths.sort();
+
+    let mut report = Report { checked: pages.len(), pages: Vec::new() };
+    for path in paths {
+        let mut page = PageReport { page: relative_name(path, output_dir), broken: Vec::new(), external: Vec::new() };
+        for url in &pages[path].urls {
+            match check_url(url, path, output_dir, base_url, &pages) {
+                Link::Fine => {},
+                Link::External => {
+                    if !page.external.contains(url) {
+                        page.external.push(url.clone());
+                    }
+                },
+                Link::Broken(reason) => page.broken.push(BrokenLink { url: url.clone(), reason }),
+            }
+        }
+        if !page.broken.is_empty() || !page.external.is_empty() {
+            report.pages.push(page);
+        }
+    }
+    Ok(report)
+}
+
+enum Link {
+    Fine,
+    External,
+    Broken(String),
+}
+
+fn check_url(url: &str, page: &Path, output_dir: &Path, base_url: &str, pages: &HashMap<PathBuf, Scan>) -> Link {
+    let base_url = base_url.trim_end_matches('/');
+    let url = match url.strip_prefix(base_url) {
+        Some(rest) if !base_url.is_empty() && (rest.is_empty() || rest.starts_with(['/', '?', '#'])) => {
+            if rest.is_empty() { "/" } else { rest }
+        },
+        _ => url,
+    };
+
+    if url.starts_with("//") || url.starts_with("http:") || url.starts_with("https:") {
+        return Link::External;
+    }
+    // Other schemes, like `mailto:` or `data:`, have nothing to check.
+    if has_scheme(url) {
+        return Link::Fine;
+    }
+
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let url = url.split_once('?').map(|(url, _)| url).unwrap_or(url);
+    let path = percent_decode(url);
+
+    let target = if path.is_empty() {
+        page.to_path_buf()
+    } else {
+        let mut target = match path.strip_prefix('/') {
+            Some(path) => output_dir.join(path),
+            None => page.parent().unwrap_or(output_dir).join(&path),
+        };
+        if path.ends_with('/') || target.is_dir() {
+            target.push("index.html");
+        }
+        target
+    };
+
+    let Some(scan) = pages.get(&normalize(&target)) else {
+        return if target.is_file() {
+            // Anchors can only be checked on HTML pages.
+            Link::Fine
+        } else {
+            Link::Broken(format!("there is no file {}", relative_name(&target, output_dir)))
+        };
+    };
+    match fragment.map(percent_decode) {
+        // `#` and `#top` go to the top of any page.
+        Some(fragment) if !fragment.is_empty() && fragment != "top" && !scan.ids.contains(&fragment) => {
+            Link::Broken(format!("{} has no element with the id `{}`", relative_name(&target, output_dir), fragment))
+        },
+        _ => Link::Fine,
+    }
+}
+
+fn is_html(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "html" || extension == "htm")
+}
+
+fn has_scheme(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) => {
+            !scheme.is_empty()
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        },
+        None => false,
+    }
+}
+
+fn relative_name(path: &Path, output_dir: &Path) -> String {
+    let path = normalize(path);
+    let relative = path.strip_prefix(normalize(output_dir)).unwrap_or(&path);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+// Resolves the `.` and `..` in a path without touching the file system, so
+// links to files that don't exist still come out the same way.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {},
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// The ids defined on a page and the URLs it refers to.
+#[derive(Debug, Default, PartialEq)]
+struct Scan {
+    ids: HashSet<String>,
+    urls: Vec<String>,
+}
+
+// Reads the tags out of an HTML page. This isn't a full HTML parser, but it
+// knows enough to skip comments and the insides of scripts and stylesheets.
+fn scan(html: &str) -> Scan {
+    let mut scan = Scan::default();
+    let mut position = 0;
+    while let Some(offset) = html[position..].find('<') {
+        let start = position + offset;
+        let rest = &html[start..];
+        if rest.starts_with("<!--") {
+            position = rest.find("-->").map_or(html.len(), |end| start + end + 3);
+            continue;
+        }
+
+        let name_length = rest[1..].find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len() - 1);
+        let name = rest[1..1 + name_length].to_ascii_lowercase();
+        if name.is_empty() {
+            position = start + 1;
+            continue;
+        }
+        let (attributes, length) = attributes(&rest[1 + name_length..]);
+        position = start + 1 + name_length + length;
+
+        for (attribute, value) in attributes {
+            match attribute.as_str() {
+                "id" => {
+                    scan.ids.insert(value);
+                },
+                // Old-style anchors, `<a name="...">`.
+                "name" if name == "a" => {
+                    scan.ids.insert(value);
+                },
+                "href" | "src" | "poster" if !value.trim().is_empty() => scan.urls.push(value.trim().to_string()),
+                "srcset" => {
+                    let candidates = value.split(',').filter_map(|candidate| candidate.split_whitespace().next());
+                    scan.urls.extend(candidates.map(str::to_string));
+                },
+                _ => {},
+            }
+        }
+
+        if name == "script" || name == "style" {
+            let close = format!("</{}", name);
+            position = html[position..].to_ascii_lowercase().find(&close).map_or(html.len(), |end| position + end);
+        }
+    }
+    scan
+}
+
+// Reads the attributes of a tag up to its closing `>`, returning them with
+// their names in lowercase and their values unescaped, along with how much of
+// `tag` they took up.
+fn attributes(tag: &str) -> (Vec<(String, String)>, usize) {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() || rest.starts_with('>') {
+            break;
+        }
+
+        let name_end = rest.find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/')).unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let Some(after_equals) = rest.strip_prefix('=') else {
+            attributes.push((name, String::new()));
+            continue;
+        };
+        let after_equals = after_equals.trim_start();
+        let (value, remaining) = match after_equals.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let inside = &after_equals[1..];
+                match inside.find(quote) {
+                    Some(end) => (&inside[..end], &inside[end + 1..]),
+                    None => (inside, ""),
+                }
+            },
+            _ => {
+                let end = after_equals.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(after_equals.len());
+                after_equals.split_at(end)
+            },
+        };
+        attributes.push((name, unescape(value)));
+        rest = remaining;
+    }
+    let length = tag.len() - rest.len() + usize::from(rest.starts_with('>'));
+    (attributes, length)
+}
+
+// Undoes the escaping `tera::escape_html` and Markdown renderers do in attributes.
+fn unescape(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_string();
+    }
+    value
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&#x2F;", "/")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_scan() {
+        let scan = scan(
+            "<!DOCTYPE html><html><head><link rel=stylesheet href=/style.css>\
+             <script>if (a <b) { x = '<a href=\"/nope\">'; }</script></head>\
+             <body><h1 ID='intro'>Hi</h1><!-- <a href=\"/old\"> -->\
+             <a name=\"legacy\"></a><a href=\"/a?x=1&amp;y=2\" class=\"x\">a</a>\
+             <img src=\"cat.png\" srcset=\"cat-1x.png 1x, cat-2x.png 2x\" alt=\"a > b\"/></body></html>",
+        );
+
+        assert_eq!(scan.ids, HashSet::from([String::from("intro"), String::from("legacy")]));
+        assert_eq!(scan.urls, vec!["/style.css", "/a?x=1&y=2", "cat.png", "cat-1x.png", "cat-2x.png"]);
+    }
+
+    #[test]
+    fn test_check_output() {
+        let dir = tempdir().unwrap();
+        write(dir.path(), "index.html", "<a href=\"/blog/\">blog</a> <a href=\"https://example.com/about.html#team\">about</a>");
+        write(
+            dir.path(),
+            "about.html",
+            "<h2 id=\"team\">Team</h2><a href=\"#team\">x</a> <a href=\"#top\">top</a> <a href=\"mailto:me@example.com\">mail</a>",
+        );
+        write(
+            dir.path(),
+            "blog/index.html",
+            "<link href=\"../style.css\" rel=\"stylesheet\"><img src=\"missing.png\">\
+             <a href=\"/about.html#people\">a</a> <a href=\"https://rust-lang.org\">b</a> <a href=\"../nowhere/\">c</a>",
+        );
+        write(dir.path(), "style.css", "body {}");
+
+        let report = check_output(dir.path(), "https://example.com").unwrap();
+
+        assert_eq!(report.checked, 3);
+        assert_eq!(
+            report.pages,
+            vec![PageReport {
+                page: String::from("blog/index.html"),
+                broken: vec![
+                    BrokenLink { url: String::from("missing.png"), reason: String::from("there is no file blog/missing.png") },
+                    BrokenLink {
+                        url: String::from("/about.html#people"),
+                        reason: String::from("about.html has no element with the id `people`"),
+                    },
+                    BrokenLink {
+                        url: String::from("../nowhere/"),
+                        reason: String::from("there is no file nowhere/index.html"),
+                    },
+                ],
+                external: vec![String::from("https://rust-lang.org")],
+            }]
+        );
+        assert_eq!(report.broken_count(), 3);
+        assert_eq!(
+            report.to_human(),
+            "blog/index.html\n\
+             \x20 `missing.png`: there is no file blog/missing.png\n\
+             \x20 `/about.html#people`: about.html has no element with the id `people`\n\
+             \x20 `../nowhere/`: there is no file nowhere/index.html\n\
+             External URLs (not checked):\n\
+             \x20 https://rust-lang.org\n\
+             Checked 3 pages: 3 broken links on 1 pages, 1 external URLs\n"
+        );
+    }
+
+    #[test]
+    fn test_check_output_in_dot_relative_dir() {
+        // `./`-prefixed output dirs, like the default `./output`, find anchors too.
+        let output_dir = Path::new("./tests/check_dot_output");
+        fs::create_dir_all(output_dir).unwrap();
+        write(output_dir, "a.html", "<a href=\"/b.html#nope\">b</a> <a href=\"b.html#here\">b</a>");
+        write(output_dir, "b.html", "<p id=\"here\">Here</p>");
+
+        let report = check_output(output_dir, "").unwrap();
+        fs::remove_dir_all(output_dir).unwrap();
+
+        assert_eq!(report.checked, 2);
+        assert_eq!(
+            report.pages,
+            vec![PageReport {
+                page: String::from("a.html"),
+                broken: vec![BrokenLink {
+                    url: String::from("/b.html#nope"),
+                    reason: String::from("b.html has no element with the id `nope`"),
+                }],
+                external: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_output_needs_a_build() {
+        let dir = tempdir().unwrap();
+        let error = check_output(&dir.path().join("public"), "").unwrap_err();
+
+        assert!(error.to_string().contains("build the site first"));
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use pulldown_cmark::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::highlight;
+
+/// The name of the config file looked for in the project root.
+pub const DEFAULT_CONFIG_FILE: &str = "rusty_ssg.toml";
+
+/// Site wide settings read from `rusty_ssg.toml`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub content_dir: String,
+    pub template_dir: String,
+    pub static_dir: String,
+    pub output_dir: String,
+    /// Where the `{language}.toml` files of strings for `trans()` are.
+    pub i18n_dir: String,
+    /// Holds the `.scss` and `.sass` stylesheets compiled to CSS in the output.
+    pub sass_dir: String,
+    pub base_url: String,
+    pub title: String,
+    pub author: Option<String>,
+    /// The language of the site, and of every page that doesn't say otherwise.
+    pub language: String,
+    /// The other languages pages can be written in, e.g. `[languages.de]`.
+    pub languages: BTreeMap<String, LanguageConfig>,
+    pub url_style: UrlStyle,
+    /// Permalink patterns keyed by section, e.g. `blog = "/blog/:year/:slug/"`.
+    pub permalinks: BTreeMap<String, String>,
+    /// The taxonomies pages can be grouped by, declared as `[[taxonomies]]` tables.
+    pub taxonomies: Vec<TaxonomyConfig>,
+    pub feeds: FeedConfig,
+    pub markdown: MarkdownConfig,
+    pub highlighting: HighlightConfig,
+    pub images: ImagesConfig,
+    pub sass: SassConfig,
+    pub extra: BTreeMap<String, tera::Value>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            content_dir: String::from("./content"),
+            template_dir: String::from("./templates"),
+            static_dir: String::from("./static"),
+            output_dir: String::from("./output"),
+            i18n_dir: String::from("./i18n"),
+            sass_dir: String::from("./sass"),
+            base_url: String::new(),
+            title: String::new(),
+            author: None,
+            language: String::from("en"),
+            languages: BTreeMap::new(),
+            url_style: UrlStyle::default(),
+            permalinks: BTreeMap::new(),
+            taxonomies: Vec::new(),
+            feeds: FeedConfig::default(),
+            markdown: MarkdownConfig::default(),
+            highlighting: HighlightConfig::default(),
+            images: ImagesConfig::default(),
+            sass: SassConfig::default(),
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+/// A language besides the default one. Its pages are written under `/{code}/`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguageConfig {
+    /// The title of the site in this language, instead of the default `title`.
+    pub title: Option<String>,
+}
+
+/// Whether pages are written as `foo.html` or as `foo/index.html`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlStyle {
+    #[default]
+    Ugly,
+    Pretty,
+}
+
+/// A way of grouping pages, such as tags or categories. Pages list their terms in
+/// their front matter under `taxonomies`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaxonomyConfig {
+    pub name: String,
+    /// Splits the page of each term into pages listing this many pages each.
+    pub paginate_by: Option<usize>,
+    /// Writes feeds for each term as well as for the whole site.
+    pub feeds: bool,
+}
+
+/// Which RSS and Atom feeds to write and what goes in them.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    /// Writes `rss.xml`.
+    pub rss: bool,
+    /// Writes `atom.xml`.
+    pub atom: bool,
+    /// The most pages a feed lists, newest first.
+    pub limit: usize,
+    /// Puts whole pages in the feeds instead of their summaries.
+    pub full_content: bool,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            rss: false,
+            atom: false,
+            limit: 20,
+            full_content: false,
+        }
+    }
+}
+
+impl FeedConfig {
+    pub fn enabled(&self) -> bool {
+        self.rss || self.atom
+    }
+}
+
+/// The Markdown extensions turned on for every page.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownConfig {
+    pub tables: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub tasklists: bool,
+    pub smart_punctuation: bool,
+    /// Reads `{#id .class}` after a heading's text as its attributes.
+    pub heading_attributes: bool,
+    /// Adds a link to itself to every heading.
+    pub heading_anchors: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        MarkdownConfig {
+            tables: true,
+            footnotes: true,
+            strikethrough: false,
+            tasklists: false,
+            smart_punctuation: false,
+            heading_attributes: true,
+            heading_anchors: false,
+        }
+    }
+}
+
+impl MarkdownConfig {
+    pub fn options(&self) -> Options {
+        let mut options = Options::empty();
+        options.set(Options::ENABLE_TABLES, self.tables);
+        options.set(Options::ENABLE_FOOTNOTES, self.footnotes);
+        options.set(Options::ENABLE_STRIKETHROUGH, self.strikethrough);
+        options.set(Options::ENABLE_TASKLISTS, self.tasklists);
+        options.set(Options::ENABLE_SMART_PUNCTUATION, self.smart_punctuation);
+        options.set(Options::ENABLE_HEADING_ATTRIBUTES, self.heading_attributes);
+        options
+    }
+}
+
+/// How fenced code blocks are highlighted.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightConfig {
+    pub enabled: bool,
+    pub style: HighlightStyle,
+    /// One of the themes that come with syntect, e.g. `base16-ocean.dark`.
+    pub theme: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        HighlightConfig {
+            enabled: false,
+            style: HighlightStyle::default(),
+            theme: String::from("base16-ocean.dark"),
+        }
+    }
+}
+
+/// Whether highlighted code carries its colours in `style` attributes or in
+/// classes styled by a stylesheet from `rusty_ssg theme-css`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightStyle {
+    #[default]
+    Inline,
+    Classes,
+}
+
+/// How images are resized and converted for `resize_image()` and, with
+/// `markdown` set, for the images in Markdown.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// Replaces Markdown images of files in the site with responsive ones.
+    pub markdown: bool,
+    /// The widths, in pixels, images are resized to. Images are never made wider
+    /// than they are.
+    pub widths: Vec<u32>,
+    /// The formats written besides the image's own, best first.
+    pub formats: Vec<ImageFormat>,
+    /// From 1 to 100, for the formats that lose quality.
+    pub quality: u8,
+    /// The `sizes` attribute, telling browsers how wide the image is shown.
+    pub sizes: String,
+    /// Where processed images are kept between builds.
+    pub cache_dir: String,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        ImagesConfig {
+            markdown: false,
+            widths: vec![480, 960, 1600],
+            formats: vec![ImageFormat::Avif, ImageFormat::Webp],
+            quality: 80,
+            sizes: String::from("100vw"),
+            cache_dir: String::from("./.cache/images"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Avif,
+    Webp,
+}
+
+/// How the stylesheets in the sass directory are compiled.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SassConfig {
+    /// Leaves out the whitespace and comments.
+    pub compressed: bool,
+}
+
+impl Config {
+    /// Loads the config file at `path`.
+    ///
+    /// A missing file is only an error when `required` is set, i.e. when the user
+    /// asked for that file by name. Otherwise the defaults are used.
+    pub fn load(path: &Path, required: bool) -> Result<Config> {
+        if !path.exists() {
+            if required {
+                bail!("config file {} does not exist", path.display());
+            }
+            return Ok(Config::default());
+        }
+
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let mut config = Config::parse(&text)
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+
+        // Directories in the config file are relative to the file itself, not to
+        // wherever the generator happens to be run from.
+        if let Some(root) = path.parent().filter(|root| !root.as_os_str().is_empty()) {
+            for dir in [
+                &mut config.content_dir,
+                &mut config.template_dir,
+                &mut config.static_dir,
+                &mut config.output_dir,
+                &mut config.i18n_dir,
+                &mut config.sass_dir,
+                &mut config.images.cache_dir,
+            ] {
+                *dir = root.join(&*dir).display().to_string();
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Config> {
+        let config: Config = toml::from_str(text)?;
+
+        let mut names = HashSet::new();
+        for taxonomy in &config.taxonomies {
+            if taxonomy.name.is_empty() {
+                bail!("every taxonomy needs a name");
+            }
+            if !names.insert(&taxonomy.name) {
+                bail!("taxonomy `{}` is declared more than once", taxonomy.name);
+            }
+        }
+        if config.highlighting.enabled {
+            highlight::theme(&config.highlighting.theme)?;
+        }
+        if config.images.widths.contains(&0) {
+            bail!("image widths have to be more than 0");
+        }
+        if !(1..=100).contains(&config.images.quality) {
+            bail!("image quality is {}, it has to be between 1 and 100", config.images.quality);
+        }
+        for code in config.languages.keys() {
+            if *code == config.language {
+                bail!("`{}` is the default language, it can't be one of the `languages` too", code);
+            }
+            if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                bail!("`{}` isn't a language code, they can only have letters, digits and `-`", code);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Every language of the site, the default one first.
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages = vec![self.language.as_str()];
+        languages.extend(self.languages.keys().map(String::as_str));
+        languages
+    }
+
+    pub fn is_language(&self, code: &str) -> bool {
+        code == self.language || self.languages.contains_key(code)
+    }
+
+    /// The title of the site in the language `code`.
+    pub fn title_in(&self, code: &str) -> &str {
+        match self.languages.get(code).and_then(|language| language.title.as_deref()) {
+            Some(title) => title,
+            None => &self.title,
+        }
+    }
+
+    /// Where the output for `directory` goes in the language `code`: the
+    /// default language's goes where it is, and the others' under their code,
+    /// e.g. `de/blog`.
+    pub fn language_directory(&self, code: &str, directory: &str) -> String {
+        let directory = directory.trim_start_matches('/');
+        if code == self.language {
+            directory.to_string()
+        } else if directory.is_empty() {
+            code.to_string()
+        } else {
+            format!("{}/{}", code, directory)
+        }
+    }
+
+    pub fn taxonomy(&self, name: &str) -> Option<&TaxonomyConfig> {
+        self.taxonomies.iter().find(|taxonomy| taxonomy.name == name)
+    }
+
+    /// Finds the permalink pattern for a section, falling back to the pattern of
+    /// the closest parent section that has one.
+    pub fn permalink_pattern(&self, section: &str) -> Option<&str> {
+        let mut section = section;
+        loop {
+            if let Some(pattern) = self.permalinks.get(section) {
+                return Some(pattern);
+            }
+            match section.rfind('/') {
+                Some(index) => section = &section[..index],
+                None if !section.is_empty() => section = "",
+                None => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r#"
+            title = "My Site"
+            base_url = "https://example.com"
+            output_dir = "public"
+
+            [markdown]
+            strikethrough = true
+
+            [extra]
+            twitter = "@me"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.title, "My Site");
+        assert_eq!(config.base_url, "https://example.com");
+        assert_eq!(config.output_dir, "public");
+        assert_eq!(config.content_dir, "./content");
+        assert_eq!(config.extra["twitter"], tera::Value::from("@me"));
+        assert!(config.markdown.options().contains(Options::ENABLE_STRIKETHROUGH));
+        assert!(config.markdown.options().contains(Options::ENABLE_TABLES));
+    }
+
+    #[test]
+    fn test_url_settings() {
+        let config = Config::parse(
+            r#"
+            url_style = "pretty"
+
+            [permalinks]
+            blog = "/blog/:year/:slug/"
+            "docs/api" = "/api/:slug/"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.url_style, UrlStyle::Pretty);
+        assert_eq!(config.permalink_pattern("blog/2024"), Some("/blog/:year/:slug/"));
+        assert_eq!(config.permalink_pattern("docs/api"), Some("/api/:slug/"));
+        assert_eq!(config.permalink_pattern("docs"), None);
+        assert_eq!(config.permalink_pattern(""), None);
+        assert!(Config::parse("url_style = \"fancy\"").is_err());
+    }
+
+    #[test]
+    fn test_languages() {
+        let config = Config::parse(
+            r#"
+            title = "Docs"
+            language = "en"
+
+            [languages.de]
+            title = "Doku"
+
+            [languages.ja]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.languages(), vec!["en", "de", "ja"]);
+        assert!(config.is_language("ja") && !config.is_language("fr"));
+        assert_eq!(config.title_in("de"), "Doku");
+        assert_eq!(config.title_in("ja"), "Docs");
+        assert_eq!(config.language_directory("en", "docs"), "docs");
+        assert_eq!(config.language_directory("de", "/docs"), "de/docs");
+        assert_eq!(config.language_directory("de", ""), "de");
+
+        let error = Config::parse("[languages.en]").unwrap_err();
+        assert_eq!(error.to_string(), "`en` is the default language, it can't be one of the `languages` too");
+        assert!(Config::parse("[languages.\"de/at\"]").is_err());
+    }
+
+    #[test]
+    fn test_images() {
+        let config = Config::parse("[images]\nmarkdown = true\nwidths = [320, 640]\nformats = [\"webp\"]").unwrap();
+
+        assert!(config.images.markdown);
+        assert_eq!(config.images.widths, vec![320, 640]);
+        assert_eq!(config.images.formats, vec![ImageFormat::Webp]);
+        assert_eq!(config.images.quality, 80);
+        assert!(Config::parse("[images]\nformats = [\"bmp\"]").is_err());
+        assert!(Config::parse("[images]\nquality = 0").is_err());
+        assert!(Config::parse("[images]\nwidths = [0]").is_err());
+    }
+
+    #[test]
+    fn test_taxonomies() {
+        let config = Config::parse(
+            r#"
+            [[taxonomies]]
+            name = "tags"
+
+            [[taxonomies]]
+            name = "categories"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.taxonomies.len(), 2);
+        assert!(config.taxonomy("categories").is_some());
+        assert!(config.taxonomy("authors").is_none());
+
+        let error = Config::parse("[[taxonomies]]\nname = \"tags\"\n[[taxonomies]]\nname = \"tags\"").unwrap_err();
+        assert_eq!(error.to_string(), "taxonomy `tags` is declared more than once");
+        assert!(Config::parse("[[taxonomies]]\nname = \"\"").is_err());
+    }
+
+    #[test]
+    fn test_highlighting() {
+        let config = Config::parse("[highlighting]\nenabled = true\nstyle = \"classes\"\ntheme = \"InspiredGitHub\"").unwrap();
+        assert_eq!(config.highlighting.style, HighlightStyle::Classes);
+
+        let error = Config::parse("[highlighting]\nenabled = true\ntheme = \"neon\"").unwrap_err();
+        assert!(error.to_string().starts_with("unknown highlighting theme `neon`"), "{}", error);
+    }
+
+    #[test]
+    fn test_unknown_key_is_an_error() {
+        let error = Config::parse("titel = \"Oops\"").unwrap_err();
+
+        assert!(error.to_string().contains("unknown field `titel`"), "{}", error);
+    }
+
+    #[test]
+    fn test_bad_type_is_an_error() {
+        let error = Config::parse("[markdown]\ntables = \"yes\"").unwrap_err();
+
+        assert!(error.to_string().contains("invalid type"), "{}", error);
+    }
+
+    #[test]
+    fn test_load_missing_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_CONFIG_FILE);
+
+        assert_eq!(Config::load(&path, false).unwrap(), Config::default());
+        assert!(Config::load(&path, true).is_err());
+    }
+
+    #[test]
+    fn test_load_resolves_dirs_next_to_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_CONFIG_FILE);
+        fs::write(&path, "content_dir = \"pages\"").unwrap();
+
+        let config = Config::load(&path, true).unwrap();
+
+        assert_eq!(Path::new(&config.content_dir), dir.path().join("pages"));
+    }
+}
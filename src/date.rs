@@ -0,0 +1,47 @@
+use anyhow::{Result, bail};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+
+/// Parses a date from front matter. Dates can be full RFC 3339 timestamps, or
+/// leave out the time zone or the time altogether, in which case they are taken
+/// to be UTC and midnight.
+pub fn parse_date(text: &str) -> Result<DateTime<FixedOffset>> {
+    let text = text.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Ok(date);
+    }
+    // TOML dates come through with a space between the date and the time.
+    if let Ok(date) = DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%:z") {
+        return Ok(date);
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(date.and_utc().fixed_offset());
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Ok(date.and_time(Default::default()).and_utc().fixed_offset());
+    }
+    bail!("`{}` is not a date, expected something like `2024-03-09` or `2024-03-09T10:30:00+01:00`", text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2024-03-09").unwrap().to_rfc3339(), "2024-03-09T00:00:00+00:00");
+        assert_eq!(parse_date("2024-03-09T10:30").unwrap().to_rfc3339(), "2024-03-09T10:30:00+00:00");
+        assert_eq!(parse_date("2024-03-09 10:30:05").unwrap().to_rfc3339(), "2024-03-09T10:30:05+00:00");
+        assert_eq!(parse_date("2024-03-09T10:30:00+01:00").unwrap().to_rfc3339(), "2024-03-09T10:30:00+01:00");
+        assert_eq!(parse_date("1979-05-27 07:32:00-08:00").unwrap().to_rfc3339(), "1979-05-27T07:32:00-08:00");
+        assert_eq!(parse_date("2024-03-09T10:30:00Z").unwrap().to_rfc3339(), "2024-03-09T10:30:00+00:00");
+    }
+
+    #[test]
+    fn test_parse_date_errors() {
+        let error = parse_date("March 2024").unwrap_err();
+        assert!(error.to_string().starts_with("`March 2024` is not a date"), "{}", error);
+        assert!(parse_date("2024-02-30").is_err());
+    }
+}
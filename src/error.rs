@@ -0,0 +1,186 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// The step of the build a page failed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Read,
+    Parse,
+    Render,
+    Write,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Read => "read",
+            Stage::Parse => "parse",
+            Stage::Render => "render",
+            Stage::Write => "write",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Something that went wrong building one file of the site.
+#[derive(Debug, Error)]
+pub enum BuildError {
+    #[error("failed to read {}", .file.display())]
+    Read { file: PathBuf, #[source] cause: anyhow::Error },
+
+    #[error("failed to parse {}", .file.display())]
+    Parse { file: PathBuf, #[source] cause: anyhow::Error },
+
+    #[error("failed to render {}", .file.display())]
+    Render { file: PathBuf, #[source] cause: anyhow::Error },
+
+    #[error("failed to write {}", .file.display())]
+    Write { file: PathBuf, #[source] cause: anyhow::Error },
+}
+
+impl BuildError {
+    pub fn new(stage: Stage, file: impl Into<PathBuf>, cause: impl Into<anyhow::Error>) -> BuildError {
+        let file = file.into();
+        let cause = cause.into();
+        match stage {
+            Stage::Read => BuildError::Read { file, cause },
+            Stage::Parse => BuildError::Parse { file, cause },
+            Stage::Render => BuildError::Render { file, cause },
+            Stage::Write => BuildError::Write { file, cause },
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        match self {
+            BuildError::Read { file, .. }
+            | BuildError::Parse { file, .. }
+            | BuildError::Render { file, .. }
+            | BuildError::Write { file, .. } => file,
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        match self {
+            BuildError::Read { .. } => Stage::Read,
+            BuildError::Parse { .. } => Stage::Parse,
+            BuildError::Render { .. } => Stage::Render,
+            BuildError::Write { .. } => Stage::Write,
+        }
+    }
+
+    pub fn cause(&self) -> &anyhow::Error {
+        match self {
+            BuildError::Read { cause, .. }
+            | BuildError::Parse { cause, .. }
+            | BuildError::Render { cause, .. }
+            | BuildError::Write { cause, .. } => cause,
+        }
+    }
+}
+
+/// Every error from a failed build, in the order the files were found in.
+#[derive(Debug, Error)]
+pub struct BuildErrors(pub Vec<BuildError>);
+
+impl fmt::Display for BuildErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.0.len();
+        write!(f, "the build failed with {} error{}", count, if count == 1 { "" } else { "s" })?;
+        for error in &self.0 {
+            write!(f, "\n  {} [{}]: {:#}", error.file().display(), error.stage(), error.cause())?;
+        }
+        Ok(())
+    }
+}
+
+/// Gathers the errors of a build. Normally the build carries on past errors so
+/// they can all be reported together; with `fail_fast` it stops at the first.
+pub struct ErrorCollector {
+    errors: Vec<BuildError>,
+    fail_fast: bool,
+}
+
+impl ErrorCollector {
+    pub fn new(fail_fast: bool) -> ErrorCollector {
+        ErrorCollector { errors: Vec::new(), fail_fast }
+    }
+
+    pub fn fail_fast(&self) -> bool {
+        self.fail_fast
+    }
+
+    /// Records `errors`, returning every error so far if the build should stop.
+    pub fn extend(&mut self, errors: impl IntoIterator<Item = BuildError>) -> Result<(), BuildErrors> {
+        self.errors.extend(errors);
+        if self.fail_fast && !self.errors.is_empty() {
+            return Err(BuildErrors(std::mem::take(&mut self.errors)));
+        }
+        Ok(())
+    }
+
+    /// Records the error of `result`, if any, passing its value on.
+    pub fn check<T>(&mut self, result: Result<T, BuildError>) -> Result<Option<T>, BuildErrors> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) => self.extend([error]).map(|()| None),
+        }
+    }
+
+    /// Gives up on the build, returning the errors collected so far.
+    pub fn abort(&mut self) -> BuildErrors {
+        BuildErrors(std::mem::take(&mut self.errors))
+    }
+
+    pub fn finish(self) -> Result<(), BuildErrors> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(BuildErrors(self.errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_build_error() {
+        let error = BuildError::new(Stage::Parse, "content/a.md", anyhow!("bad front matter"));
+
+        assert_eq!(error.to_string(), "failed to parse content/a.md");
+        assert_eq!(error.file(), Path::new("content/a.md"));
+        assert_eq!(error.stage(), Stage::Parse);
+        assert_eq!(std::error::Error::source(&error).unwrap().to_string(), "bad front matter");
+    }
+
+    #[test]
+    fn test_build_errors_summary() {
+        let errors = BuildErrors(vec![
+            BuildError::new(Stage::Read, "content/a.md", anyhow!("No such file").context("outer")),
+            BuildError::new(Stage::Render, "content/b.md", anyhow!("unknown variable")),
+        ]);
+
+        assert_eq!(
+            errors.to_string(),
+            "the build failed with 2 errors\n  content/a.md [read]: outer: No such file\n  content/b.md [render]: unknown variable"
+        );
+    }
+
+    #[test]
+    fn test_error_collector() {
+        let mut collector = ErrorCollector::new(false);
+        assert_eq!(collector.check(Ok::<_, BuildError>(1)).unwrap(), Some(1));
+        assert_eq!(collector.check(Err::<i32, _>(BuildError::new(Stage::Read, "a.md", anyhow!("x")))).unwrap(), None);
+        collector.extend([BuildError::new(Stage::Write, "b.md", anyhow!("y"))]).unwrap();
+        assert_eq!(collector.finish().unwrap_err().0.len(), 2);
+
+        let mut collector = ErrorCollector::new(true);
+        assert!(collector.extend([]).is_ok());
+        let errors = collector.extend([BuildError::new(Stage::Read, "a.md", anyhow!("x"))]).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+    }
+}
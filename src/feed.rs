@@ -0,0 +1,351 @@
+use std::cmp::Reverse;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::config::Config;
+use crate::page::Page;
+
+/// The pages of one feed along with what the feed is about.
+#[derive(Debug)]
+pub struct Feed<'a> {
+    /// The language of the feed's pages.
+    pub lang: String,
+    pub title: String,
+    pub description: String,
+    /// The HTML page the feed belongs to, e.g. the blog's index page.
+    pub link: String,
+    /// Where the feed files go relative to the site root, e.g. `blog`.
+    pub directory: String,
+    /// The newest dated pages, newest first.
+    pub pages: Vec<&'a Page>,
+}
+
+impl<'a> Feed<'a> {
+    /// Picks the pages that go in a feed: only dated pages that aren't unlisted
+    /// or drafts, newest first, and no more than the configured limit. Drafts
+    /// built with `--drafts` are still never syndicated.
+    pub fn new(
+        config: &Config,
+        lang: &str,
+        title: &str,
+        description: Option<&str>,
+        link: &str,
+        directory: &str,
+        pages: impl IntoIterator<Item = &'a Page>,
+    ) -> Feed<'a> {
+        let mut pages: Vec<&Page> =
+            pages.into_iter().filter(|page| page.datetime.is_some() && !page.meta.unlisted && !page.meta.draft).collect();
+        pages.sort_by_key(|page| Reverse(page.datetime));
+        pages.truncate(config.feeds.limit);
+
+        Feed {
+            lang: lang.to_string(),
+            title: title.to_string(),
+            description: description.unwrap_or(title).to_string(),
+            link: link.to_string(),
+            directory: directory.trim_matches('/').to_string(),
+            pages,
+        }
+    }
+
+    /// The site-relative path of one of the feed's files, e.g. `/blog/rss.xml`.
+    pub fn path(&self, file_name: &str) -> String {
+        if self.directory.is_empty() {
+            format!("/{}", file_name)
+        } else {
+            format!("/{}/{}", self.directory, file_name)
+        }
+    }
+
+    fn url(&self, config: &Config, file_name: &str) -> String {
+        format!("{}{}", config.base_url.trim_end_matches('/'), self.path(file_name))
+    }
+
+    // The date of the newest entry. Feeds without entries have none, rather than
+    // the time they were written, so building twice gives the same feed.
+    fn updated(&self) -> Option<DateTime<FixedOffset>> {
+        self.pages.iter().filter_map(|page| page.datetime).max()
+    }
+}
+
+/// A page as it appears in a feed, with its HTML already rendered.
+#[derive(Debug)]
+pub struct FeedEntry<'a> {
+    pub page: &'a Page,
+    pub html: String,
+    /// Whether `html` is only the page's summary rather than all of it.
+    pub is_summary: bool,
+}
+
+/// Writes the feed as RSS 2.0.
+pub fn rss(config: &Config, feed: &Feed, entries: &[FeedEntry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+    xml.push_str(&element("title", &feed.title));
+    xml.push_str(&element("link", &feed.link));
+    xml.push_str(&element("description", &feed.description));
+    xml.push_str(&element("language", &feed.lang));
+    xml.push_str(&element("generator", "rusty_ssg"));
+    if let Some(updated) = feed.updated() {
+        xml.push_str(&element("lastBuildDate", &updated.to_rfc2822()));
+    }
+    xml.push_str(&format!(
+        "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        escape_xml(&feed.url(config, "rss.xml"))
+    ));
+
+    for entry in entries {
+        let page = entry.page;
+        xml.push_str("<item>\n");
+        xml.push_str(&element("title", page.meta.title.as_deref().unwrap_or_default()));
+        xml.push_str(&element("link", &page.permalink));
+        // Without a `base_url` the link is only a path, which readers can't
+        // follow on their own, so it's just an ID.
+        let is_permalink = page.permalink.contains("://");
+        xml.push_str(&format!("<guid isPermaLink=\"{}\">{}</guid>\n", is_permalink, escape_xml(&page.permalink)));
+        xml.push_str(&alternates(page, "atom:link"));
+        if let Some(date) = page.datetime {
+            xml.push_str(&element("pubDate", &date.to_rfc2822()));
+        }
+        for category in page.taxonomies.values().flatten() {
+            xml.push_str(&element("category", &category.name));
+        }
+        xml.push_str(&element("description", &entry.html));
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+/// Writes the feed as Atom.
+pub fn atom(config: &Config, feed: &Feed, entries: &[FeedEntry]) -> String {
+    let self_url = feed.url(config, "atom.xml");
+    let author = config
+        .author
+        .as_deref()
+        .or(Some(config.title.as_str()).filter(|title| !title.is_empty()))
+        .unwrap_or("Unknown");
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"{}\">\n",
+        escape_xml(&feed.lang)
+    ));
+    xml.push_str(&element("title", &feed.title));
+    xml.push_str(&element("subtitle", &feed.description));
+    xml.push_str(&format!(
+        "<link href=\"{}\" rel=\"self\" type=\"application/atom+xml\"/>\n",
+        escape_xml(&self_url)
+    ));
+    xml.push_str(&format!("<link href=\"{}\" rel=\"alternate\" type=\"text/html\"/>\n", escape_xml(&feed.link)));
+    xml.push_str(&element("generator", "rusty_ssg"));
+    // Atom feeds have to say when they were updated, so an empty one goes back to the epoch.
+    let updated = feed.updated().unwrap_or(DateTime::UNIX_EPOCH.fixed_offset());
+    xml.push_str(&element("updated", &updated.to_rfc3339()));
+    xml.push_str(&element("id", &self_url));
+    xml.push_str(&format!("<author>\n{}</author>\n", element("name", author)));
+
+    for entry in entries {
+        let page = entry.page;
+        xml.push_str("<entry>\n");
+        xml.push_str(&element("title", page.meta.title.as_deref().unwrap_or_default()));
+        xml.push_str(&format!(
+            "<link href=\"{}\" rel=\"alternate\" type=\"text/html\"/>\n",
+            escape_xml(&page.permalink)
+        ));
+        xml.push_str(&alternates(page, "link"));
+        xml.push_str(&element("id", &page.permalink));
+        if let Some(date) = page.datetime {
+            xml.push_str(&element("published", &date.to_rfc3339()));
+            xml.push_str(&element("updated", &date.to_rfc3339()));
+        }
+        for category in page.taxonomies.values().flatten() {
+            xml.push_str(&format!("<category term=\"{}\"/>\n", escape_xml(&category.name)));
+        }
+        let kind = if entry.is_summary { "summary" } else { "content" };
+        xml.push_str(&format!("<{0} type=\"html\">{1}</{0}>\n", kind, escape_xml(&entry.html)));
+        xml.push_str("</entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+// Links to the page in the other languages it is written in.
+fn alternates(page: &Page, tag: &str) -> String {
+    page.translations
+        .iter()
+        .filter(|translation| translation.lang != page.lang)
+        .map(|translation| {
+            format!(
+                "<{} href=\"{}\" rel=\"alternate\" hreflang=\"{}\" type=\"text/html\"/>\n",
+                tag,
+                escape_xml(&translation.permalink),
+                escape_xml(&translation.lang)
+            )
+        })
+        .collect()
+}
+
+fn element(name: &str, text: &str) -> String {
+    format!("<{0}>{1}</{0}>\n", name, escape_xml(text))
+}
+
+/// Escapes text for use in XML content or attribute values.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than tabs and line breaks aren't allowed in XML at all.
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::front_matter::PageMeta;
+    use crate::i18n::Translation;
+    use std::path::Path;
+
+    fn config() -> Config {
+        Config {
+            base_url: String::from("https://example.com/"),
+            title: String::from("My <Site>"),
+            ..Config::default()
+        }
+    }
+
+    fn page(config: &Config, name: &str, date: Option<&str>) -> Page {
+        let meta = PageMeta {
+            title: Some(format!("{} & co", name)),
+            date: date.map(String::from),
+            ..PageMeta::default()
+        };
+        Page::new(config, Path::new(&format!("./content/blog/{}.md", name)), "./content", meta, "").unwrap()
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(escape_xml("<p class=\"a\">Tom & Jerry's</p>\u{1}"), "&lt;p class=&quot;a&quot;&gt;Tom &amp; Jerry&apos;s&lt;/p&gt;");
+    }
+
+    #[test]
+    fn test_feed_picks_newest_dated_pages() {
+        let config = Config { feeds: crate::config::FeedConfig { limit: 2, ..Default::default() }, ..config() };
+        let mut unlisted = page(&config, "unlisted", Some("2025-01-01"));
+        unlisted.meta.unlisted = true;
+        let mut draft = page(&config, "draft", Some("2025-02-01"));
+        draft.meta.draft = true;
+        let pages = [
+            page(&config, "old", Some("2023-01-01")),
+            page(&config, "undated", None),
+            unlisted,
+            draft,
+            page(&config, "new", Some("2024-06-01T12:00:00+02:00")),
+            page(&config, "middle", Some("2024-01-01")),
+        ];
+
+        let feed = Feed::new(&config, "en", "Blog", None, "https://example.com/blog/", "/blog/", &pages);
+
+        let slugs: Vec<&str> = feed.pages.iter().map(|page| page.slug.as_str()).collect();
+        assert_eq!(slugs, vec!["new", "middle"]);
+        assert_eq!(feed.description, "Blog");
+        assert_eq!(feed.path("rss.xml"), "/blog/rss.xml");
+        assert_eq!(feed.url(&config, "atom.xml"), "https://example.com/blog/atom.xml");
+    }
+
+    #[test]
+    fn test_rss() {
+        let config = config();
+        let pages = [page(&config, "post", Some("2024-03-09"))];
+        let feed = Feed::new(&config, "en", &config.title, None, "https://example.com/", "", &pages);
+        let entries = [FeedEntry { page: &pages[0], html: String::from("<p>Hi & bye</p>"), is_summary: true }];
+
+        let xml = rss(&config, &feed, &entries);
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\""));
+        assert!(xml.contains("<title>My &lt;Site&gt;</title>"));
+        assert!(xml.contains("<atom:link href=\"https://example.com/rss.xml\" rel=\"self\""));
+        assert!(xml.contains("<lastBuildDate>Sat, 9 Mar 2024 00:00:00 +0000</lastBuildDate>"));
+        assert!(xml.contains(
+            "<item>\n<title>post &amp; co</title>\n<link>https://example.com/blog/post.html</link>\n\
+             <guid isPermaLink=\"true\">https://example.com/blog/post.html</guid>\n\
+             <pubDate>Sat, 9 Mar 2024 00:00:00 +0000</pubDate>\n\
+             <description>&lt;p&gt;Hi &amp; bye&lt;/p&gt;</description>\n</item>\n"
+        ), "{}", xml);
+        assert!(xml.ends_with("</channel>\n</rss>\n"));
+    }
+
+    #[test]
+    fn test_rss_without_base_url() {
+        let config = Config { base_url: String::new(), ..config() };
+        let pages = [page(&config, "post", Some("2024-03-09"))];
+        let feed = Feed::new(&config, "en", &config.title, None, "/", "", &pages);
+        let entries = [FeedEntry { page: &pages[0], html: String::new(), is_summary: true }];
+
+        let xml = rss(&config, &feed, &entries);
+
+        assert!(xml.contains("<guid isPermaLink=\"false\">/blog/post.html</guid>"), "{}", xml);
+    }
+
+    #[test]
+    fn test_atom() {
+        let config = Config { author: Some(String::from("Me")), ..config() };
+        let pages = [page(&config, "post", Some("2024-03-09T10:00:00+01:00"))];
+        let feed = Feed::new(&config, "en", "Blog", Some("All the posts"), "https://example.com/blog/", "blog", &pages);
+        let entries = [FeedEntry { page: &pages[0], html: String::from("<p>Hi</p>"), is_summary: false }];
+
+        let xml = atom(&config, &feed, &entries);
+
+        assert!(xml.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"en\">"));
+        assert!(xml.contains("<subtitle>All the posts</subtitle>"));
+        assert!(xml.contains("<link href=\"https://example.com/blog/atom.xml\" rel=\"self\""));
+        assert!(xml.contains("<id>https://example.com/blog/atom.xml</id>"));
+        assert!(xml.contains("<updated>2024-03-09T10:00:00+01:00</updated>\n<id>"));
+        assert!(xml.contains("<author>\n<name>Me</name>\n</author>"));
+        assert!(xml.contains("<published>2024-03-09T10:00:00+01:00</published>"));
+        assert!(xml.contains("<content type=\"html\">&lt;p&gt;Hi&lt;/p&gt;</content>"));
+        assert!(xml.ends_with("</entry>\n</feed>\n"));
+    }
+
+    #[test]
+    fn test_empty_feeds_dont_change_between_builds() {
+        let config = config();
+        let feed = Feed::new(&config, "en", "Blog", None, "https://example.com/blog/", "blog", &[]);
+
+        assert!(!rss(&config, &feed, &[]).contains("<lastBuildDate>"));
+        assert!(atom(&config, &feed, &[]).contains("<updated>1970-01-01T00:00:00+00:00</updated>"));
+    }
+
+    #[test]
+    fn test_translation_alternates() {
+        let config = config();
+        let mut post = page(&config, "post", Some("2024-03-09"));
+        let german = Translation {
+            lang: String::from("de"),
+            title: None,
+            path: String::from("/de/blog/post.html"),
+            permalink: String::from("https://example.com/de/blog/post.html"),
+        };
+        post.translations = vec![post.translation(), german];
+        let feed = Feed::new(&config, "en", "Blog", None, "https://example.com/blog/", "blog", [&post]);
+        let entries = [FeedEntry { page: &post, html: String::new(), is_summary: false }];
+
+        let alternate = "href=\"https://example.com/de/blog/post.html\" rel=\"alternate\" hreflang=\"de\" type=\"text/html\"/>\n";
+        let xml = atom(&config, &feed, &entries);
+        assert!(xml.contains(&format!("<link {}", alternate)), "{}", xml);
+        assert_eq!(xml.matches("hreflang").count(), 1);
+        let xml = rss(&config, &feed, &entries);
+        assert!(xml.contains(&format!("<atom:link {}", alternate)), "{}", xml);
+    }
+}
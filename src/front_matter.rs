@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result, bail};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::sitemap::ChangeFreq;
+
+/// Metadata read from the front matter at the top of a Markdown file.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PageMeta {
+    pub title: Option<String>,
+    /// Templates see the slug the page ends up with as `page.slug`.
+    #[serde(skip_serializing)]
+    pub slug: Option<String>,
+    pub date: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    /// Terms keyed by taxonomy, e.g. `categories: [programming]`. Templates see
+    /// these resolved into links as `page.taxonomies`.
+    #[serde(skip_serializing)]
+    pub taxonomies: BTreeMap<String, Vec<String>>,
+    pub template: Option<String>,
+    /// Where the page goes when its section is sorted by weight, lightest first.
+    pub weight: Option<i64>,
+    /// Leaves the page out of the build unless `--drafts` is passed.
+    pub draft: bool,
+    /// Holds the page back until this date, unless `--future` is passed.
+    pub publish_date: Option<String>,
+    /// Takes the page down after this date, unless `--future` is passed.
+    pub expiry_date: Option<String>,
+    /// `toc: false` leaves the page without a table of contents.
+    #[serde(skip_serializing)]
+    pub toc: Option<bool>,
+    /// Builds the page but leaves it out of section and taxonomy listings, feeds
+    /// and the sitemap.
+    pub unlisted: bool,
+    /// How important the page is compared to the rest of the site, from 0.0 to 1.0,
+    /// for the sitemap.
+    pub priority: Option<f32>,
+    pub changefreq: Option<ChangeFreq>,
+    pub extra: BTreeMap<String, tera::Value>,
+}
+
+/// Splits the front matter off the top of a Markdown file and parses it.
+///
+/// `---` delimits YAML front matter and `+++` delimits TOML front matter. Files
+/// without front matter get the default metadata and their full text back.
+pub fn split_front_matter(text: &str) -> Result<(PageMeta, &str)> {
+    parse_front_matter(text)
+}
+
+/// Like [`split_front_matter`], for files whose front matter isn't a page's, such
+/// as a section's `_index.md`.
+pub fn parse_front_matter<T: DeserializeOwned + Default>(text: &str) -> Result<(T, &str)> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+
+    let (first_line, rest) = split_line(text);
+    let delimiter = first_line.trim_end();
+    if delimiter != "---" && delimiter != "+++" {
+        return Ok((T::default(), text));
+    }
+
+    // Walk the remaining lines looking for the matching closing delimiter.
+    let mut offset = 0;
+    loop {
+        if offset >= rest.len() {
+            bail!("front matter opened with `{}` is never closed", delimiter);
+        }
+        let (line, _) = split_line(&rest[offset..]);
+        if line.trim_end() == delimiter {
+            let front_matter = &rest[..offset];
+            let body = &rest[offset..][line.len()..];
+            let body = body.strip_prefix("\r\n").or_else(|| body.strip_prefix('\n')).unwrap_or(body);
+
+            let meta = if delimiter == "---" {
+                parse_yaml(front_matter)?
+            } else {
+                parse_toml(front_matter)?
+            };
+            return Ok((meta, body));
+        }
+        offset += line.len();
+        offset += if rest[offset..].starts_with("\r\n") { 2 } else { 1 };
+    }
+}
+
+// Returns the first line of `text` (without its line ending) and everything after it.
+fn split_line(text: &str) -> (&str, &str) {
+    match text.find('\n') {
+        Some(index) => (text[..index].trim_end_matches('\r'), &text[index + 1..]),
+        None => (text, ""),
+    }
+}
+
+fn parse_yaml<T: DeserializeOwned + Default>(front_matter: &str) -> Result<T> {
+    if front_matter.trim().is_empty() {
+        return Ok(T::default());
+    }
+    serde_yaml::from_str(front_matter).context("invalid YAML front matter")
+}
+
+fn parse_toml<T: DeserializeOwned>(front_matter: &str) -> Result<T> {
+    let table: toml::Table = toml::from_str(front_matter).context("invalid TOML front matter")?;
+
+    // TOML has a native date type; turn those into plain strings so dates look the
+    // same to the rest of the generator whichever front matter format was used.
+    stringify_dates(toml::Value::Table(table))
+        .try_into()
+        .context("invalid TOML front matter")
+}
+
+fn stringify_dates(value: toml::Value) -> toml::Value {
+    match value {
+        toml::Value::Datetime(datetime) => toml::Value::String(datetime.to_string()),
+        toml::Value::Array(values) => {
+            toml::Value::Array(values.into_iter().map(stringify_dates).collect())
+        }
+        toml::Value::Table(table) => toml::Value::Table(
+            table.into_iter().map(|(key, value)| (key, stringify_dates(value))).collect(),
+        ),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_front_matter() {
+        let (meta, body) = split_front_matter("# Hello\n\nWorld").unwrap();
+
+        assert_eq!(meta, PageMeta::default());
+        assert_eq!(body, "# Hello\n\nWorld");
+    }
+
+    #[test]
+    fn test_yaml_front_matter() {
+        let text = "---\ntitle: Hello\ndate: 2024-01-05\ntags: [rust, ssg]\nchangefreq: monthly\nextra:\n  hero: cat.png\n---\n# Body\n";
+        let (meta, body) = split_front_matter(text).unwrap();
+
+        assert_eq!(meta.title.as_deref(), Some("Hello"));
+        assert_eq!(meta.date.as_deref(), Some("2024-01-05"));
+        assert_eq!(meta.tags, vec!["rust", "ssg"]);
+        assert_eq!(meta.extra["hero"], tera::Value::from("cat.png"));
+        assert!(!meta.draft);
+        assert_eq!(meta.changefreq, Some(ChangeFreq::Monthly));
+        assert_eq!(body, "# Body\n");
+    }
+
+    #[test]
+    fn test_toml_front_matter() {
+        let text = "+++\r\ntitle = \"Hello\"\r\ndate = 2024-01-05\r\ndraft = true\r\npriority = 0.5\r\ntemplate = \"post.html\"\r\n+++\r\nBody";
+        let (meta, body) = split_front_matter(text).unwrap();
+
+        assert_eq!(meta.title.as_deref(), Some("Hello"));
+        assert_eq!(meta.date.as_deref(), Some("2024-01-05"));
+        assert_eq!(meta.template.as_deref(), Some("post.html"));
+        assert!(meta.draft);
+        assert_eq!(meta.priority, Some(0.5));
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn test_unclosed_front_matter() {
+        assert!(split_front_matter("---\ntitle: Hello\n").is_err());
+    }
+
+    #[test]
+    fn test_invalid_front_matter() {
+        assert!(split_front_matter("+++\ntitle = \n+++\n").is_err());
+        assert!(split_front_matter("---\ntags: 5\n---\n").is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde::Serialize;
+
+use crate::slug::slugify;
+
+/// A heading in a page's table of contents, with the headings below it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TocEntry {
+    pub level: u32,
+    pub title: String,
+    /// The heading's `id`, for linking to it as `#id`.
+    pub id: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Gives every heading among `events` an `id` and, with `anchors` set, a link
+/// to itself. Headings keep the ids set with `{#custom-id}`, and the others get
+/// one made from their text, with a number added when the same text has been
+/// used before. Returns the headings nested into a table of contents.
+pub fn anchor_headings(events: Vec<Event<'_>>, anchors: bool) -> (Vec<Event<'_>>, Vec<TocEntry>) {
+    // Ids set by hand are taken before any are made up, wherever they are on the page.
+    let mut used: HashSet<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    let mut output = Vec::with_capacity(events.len());
+    let mut toc = Vec::new();
+    // The heading being read, with the events inside it.
+    let mut heading: Option<(Tag, Vec<Event>)> = None;
+    for event in events {
+        match (&mut heading, event) {
+            (None, Event::Start(tag @ Tag::Heading { .. })) => heading = Some((tag, Vec::new())),
+            (Some(_), Event::End(end @ TagEnd::Heading(_))) => {
+                let Some((Tag::Heading { level, id, classes, attrs }, inside)) = heading.take() else {
+                    unreachable!("only headings are read");
+                };
+                let title: String = inside
+                    .iter()
+                    .filter_map(|event| match event {
+                        Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                let id = match id {
+                    Some(id) => id.to_string(),
+                    None => unique_id(&mut used, &title),
+                };
+
+                output.push(Event::Start(Tag::Heading { level, id: Some(CowStr::from(id.clone())), classes, attrs }));
+                output.extend(inside);
+                if anchors {
+                    output.push(Event::InlineHtml(CowStr::from(format!(
+                        " <a class=\"anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>",
+                        tera::escape_html(&id)
+                    ))));
+                }
+                output.push(Event::End(end));
+
+                let entry = TocEntry { level: level as u32, title: title.trim().to_string(), id, children: Vec::new() };
+                add_to_toc(&mut toc, entry);
+            },
+            (Some((_, inside)), event) => inside.push(event),
+            (None, event) => output.push(event),
+        }
+    }
+    (output, toc)
+}
+
+/// The ids [`anchor_headings`] gives the headings of `markdown`, for checking
+/// links to them before the page is converted.
+pub fn heading_ids(markdown: &str, options: Options) -> HashSet<String> {
+    fn collect(entries: Vec<TocEntry>, ids: &mut HashSet<String>) {
+        for entry in entries {
+            ids.insert(entry.id);
+            collect(entry.children, ids);
+        }
+    }
+
+    let (_, toc) = anchor_headings(Parser::new_ext(markdown, options).collect(), false);
+    let mut ids = HashSet::new();
+    collect(toc, &mut ids);
+    ids
+}
+
+fn unique_id(used: &mut HashSet<String>, title: &str) -> String {
+    let mut base = slugify(title);
+    if base.is_empty() {
+        base = String::from("heading");
+    }
+    let mut id = base.clone();
+    let mut number = 1;
+    while used.contains(&id) {
+        id = format!("{}-{}", base, number);
+        number += 1;
+    }
+    used.insert(id.clone());
+    id
+}
+
+// A heading goes under the last heading before it that is at a higher level.
+fn add_to_toc(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+    match entries.last_mut() {
+        Some(last) if last.level < entry.level => add_to_toc(&mut last.children, entry),
+        _ => entries.push(entry),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::html;
+
+    fn render(markdown: &str, anchors: bool) -> (String, Vec<TocEntry>) {
+        let events = Parser::new_ext(markdown, Options::ENABLE_HEADING_ATTRIBUTES).collect();
+        let (events, toc) = anchor_headings(events, anchors);
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter());
+        (output, toc)
+    }
+
+    fn entry(level: u32, title: &str, id: &str, children: Vec<TocEntry>) -> TocEntry {
+        TocEntry { level, title: title.to_string(), id: id.to_string(), children }
+    }
+
+    #[test]
+    fn test_heading_ids() {
+        let (html, _) = render("# Intro\n\n## Setup `cargo`\n\n## Intro\n\n## Usage {#intro-1}\n\n### ???", false);
+
+        assert_eq!(
+            html,
+            "<h1 id=\"intro\">Intro</h1>\n\
+             <h2 id=\"setup-cargo\">Setup <code>cargo</code></h2>\n\
+             <h2 id=\"intro-2\">Intro</h2>\n\
+             <h2 id=\"intro-1\">Usage</h2>\n\
+             <h3 id=\"heading\">???</h3>\n"
+        );
+    }
+
+    #[test]
+    fn test_heading_anchors() {
+        let (html, _) = render("## Hello *there*", true);
+
+        assert_eq!(
+            html,
+            "<h2 id=\"hello-there\">Hello <em>there</em> <a class=\"anchor\" href=\"#hello-there\" aria-hidden=\"true\">#</a></h2>\n"
+        );
+    }
+
+    #[test]
+    fn test_heading_ids_match_anchors() {
+        let ids = heading_ids("# Intro\n\n## Intro\n\n### Setup {#install}", Options::ENABLE_HEADING_ATTRIBUTES);
+
+        assert_eq!(ids, HashSet::from([String::from("intro"), String::from("intro-1"), String::from("install")]));
+    }
+
+    #[test]
+    fn test_toc() {
+        let (_, toc) = render("## Before\n\n# One\n\n## Two\n\n### Three\n\n## Four\n\n# Five", false);
+
+        assert_eq!(
+            toc,
+            vec![
+                entry(2, "Before", "before", vec![]),
+                entry(1, "One", "one", vec![
+                    entry(2, "Two", "two", vec![entry(3, "Three", "three", vec![])]),
+                    entry(2, "Four", "four", vec![]),
+                ]),
+                entry(1, "Five", "five", vec![]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use std::ops::{Range, RangeInclusive};
+use std::sync::LazyLock;
+
+use anyhow::{Context, Result, anyhow, bail};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag, TagEnd};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{ClassStyle, IncludeBackground, css_for_theme_with_class_style, line_tokens_to_classed_spans};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::config::{HighlightConfig, HighlightStyle};
+
+// Loading these takes a while, so it's done once, the first time a code block
+// is highlighted.
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+// Classes get a prefix so they don't clash with the site's own stylesheet.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Looks up one of the themes that come with syntect by name.
+pub fn theme(name: &str) -> Result<&'static Theme> {
+    THEMES.themes.get(name).ok_or_else(|| {
+        let names: Vec<&str> = THEMES.themes.keys().map(String::as_str).collect();
+        anyhow!("unknown highlighting theme `{}`, the themes are: {}", name, names.join(", "))
+    })
+}
+
+/// The stylesheet for code highlighted with `style = "classes"`.
+pub fn theme_css(name: &str) -> Result<String> {
+    let theme = theme(name)?;
+    let mut css = css_for_theme_with_class_style(theme, CLASS_STYLE)?;
+    css.push_str(&format!(
+        "\n.hl-line-number {{\n user-select: none;\n margin-right: 1em;\n color: {};\n}}\n",
+        css_color(gutter_color(theme))
+    ));
+    css.push_str(&format!(".hl-mark {{\n background-color: {};\n}}\n", css_color(mark_color(theme))));
+    Ok(css)
+}
+
+/// The attributes a fenced code block can have after its language, separated by
+/// commas: ```` ```rust,linenos,hl_lines=1 3-5,name=src/main.rs ````.
+#[derive(Debug, Clone, PartialEq)]
+struct Fence {
+    language: Option<String>,
+    line_numbers: bool,
+    first_line: usize,
+    /// Lines to mark, counted from the top of the block starting at 1.
+    highlighted_lines: Vec<RangeInclusive<usize>>,
+    /// A file name shown above the code.
+    name: Option<String>,
+}
+
+impl Default for Fence {
+    fn default() -> Self {
+        Fence { language: None, line_numbers: false, first_line: 1, highlighted_lines: Vec::new(), name: None }
+    }
+}
+
+impl Fence {
+    fn parse(info: &str) -> Result<Fence> {
+        let mut fence = Fence::default();
+        let attributes = info.split(',').map(str::trim).filter(|attribute| !attribute.is_empty());
+        for (index, attribute) in attributes.enumerate() {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim())),
+                None => (attribute, None),
+            };
+            match (key, value) {
+                ("linenos", None) => fence.line_numbers = true,
+                ("linenos", Some(value)) => {
+                    fence.line_numbers = value
+                        .parse()
+                        .with_context(|| format!("`linenos` has to be `true` or `false`, not `{}`", value))?;
+                },
+                ("linenostart", Some(value)) => {
+                    fence.first_line = value
+                        .parse()
+                        .with_context(|| format!("`linenostart` has to be a line number, not `{}`", value))?;
+                },
+                ("hl_lines", Some(value)) => fence.highlighted_lines = parse_line_ranges(value)?,
+                ("name", Some(value)) => fence.name = Some(value.trim_matches('"').to_string()),
+                (language, None) if index == 0 => fence.language = Some(language.to_string()),
+                // Other tools have attributes of their own, like rustdoc's `ignore`
+                // and `no_run`, so anything else is left alone.
+                _ => {},
+            }
+        }
+        Ok(fence)
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted_lines.iter().any(|range| range.contains(&line))
+    }
+}
+
+// Parses line numbers and ranges separated by spaces, e.g. `1 3-5`.
+fn parse_line_ranges(value: &str) -> Result<Vec<RangeInclusive<usize>>> {
+    value
+        .split_whitespace()
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            match (start.parse::<usize>(), end.parse::<usize>()) {
+                (Ok(start), Ok(end)) if 0 < start && start <= end => Ok(start..=end),
+                _ => bail!("`hl_lines` takes line numbers and ranges like `1 3-5`, not `{}`", range),
+            }
+        })
+        .collect()
+}
+
+/// Replaces the code blocks among a page's Markdown `events` with highlighted
+/// HTML. Errors mention the line the code block starts on, counting from
+/// `first_line` at the top of `markdown`.
+pub fn highlight_code_blocks<'a>(
+    markdown: &str,
+    first_line: usize,
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)>,
+    config: &HighlightConfig,
+) -> Result<Vec<Event<'a>>> {
+    let mut output = Vec::new();
+    // The code block being read, along with the line it starts on.
+    let mut code_block: Option<(Fence, String, usize)> = None;
+    for (event, range) in events {
+        match (&mut code_block, event) {
+            (None, Event::Start(Tag::CodeBlock(kind))) => {
+                let line = first_line + markdown[..range.start].matches('\n').count();
+                let fence = match kind {
+                    CodeBlockKind::Fenced(info) => Fence::parse(&info),
+                    CodeBlockKind::Indented => Ok(Fence::default()),
+                };
+                let fence = fence.with_context(|| format!("in the code block on line {}", line))?;
+                code_block = Some((fence, String::new(), line));
+            },
+            (Some((_, code, _)), Event::Text(text)) => code.push_str(&text),
+            (Some(_), Event::End(TagEnd::CodeBlock)) => {
+                if let Some((fence, code, line)) = code_block.take() {
+                    let html = highlight(&code, &fence, config)
+                        .with_context(|| format!("in the code block on line {}", line))?;
+                    output.push(Event::Html(CowStr::from(html)));
+                }
+            },
+            (_, event) => output.push(event),
+        }
+    }
+    Ok(output)
+}
+
+fn highlight(code: &str, fence: &Fence, config: &HighlightConfig) -> Result<String> {
+    let theme = theme(&config.theme)?;
+    let syntax = fence
+        .language
+        .as_deref()
+        .and_then(|language| SYNTAXES.find_syntax_by_token(language))
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    let lines = match config.style {
+        HighlightStyle::Inline => inline_lines(code, syntax, theme)?,
+        HighlightStyle::Classes => classed_lines(code, syntax)?,
+    };
+
+    let mut html = String::new();
+    if let Some(name) = &fence.name {
+        html.push_str(&format!("<figure class=\"code-block\"><figcaption>{}</figcaption>", tera::escape_html(name)));
+    }
+    match config.style {
+        HighlightStyle::Inline => html.push_str(&format!(
+            "<pre class=\"code\" style=\"background-color:{};color:{};\"",
+            css_color(theme.settings.background.unwrap_or(Color::WHITE)),
+            css_color(theme.settings.foreground.unwrap_or(Color::BLACK)),
+        )),
+        HighlightStyle::Classes => html.push_str("<pre class=\"hl-code\""),
+    }
+    match &fence.language {
+        Some(language) => {
+            let language = tera::escape_html(language);
+            html.push_str(&format!(" data-lang=\"{0}\"><code class=\"language-{0}\">", language));
+        },
+        None => html.push_str("><code>"),
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        if fence.line_numbers {
+            let number = fence.first_line + index;
+            match config.style {
+                HighlightStyle::Inline => html.push_str(&format!(
+                    "<span class=\"hl-line-number\" style=\"user-select:none;margin-right:1em;color:{};\">{}</span>",
+                    css_color(gutter_color(theme)),
+                    number
+                )),
+                HighlightStyle::Classes => html.push_str(&format!("<span class=\"hl-line-number\">{}</span>", number)),
+            }
+        }
+        if fence.is_highlighted(index + 1) {
+            match config.style {
+                HighlightStyle::Inline => html.push_str(&format!(
+                    "<mark class=\"hl-mark\" style=\"background-color:{};color:inherit;\">{}</mark>",
+                    css_color(mark_color(theme)),
+                    line
+                )),
+                HighlightStyle::Classes => html.push_str(&format!("<mark class=\"hl-mark\">{}</mark>", line)),
+            }
+        } else {
+            html.push_str(line);
+        }
+        html.push('\n');
+    }
+
+    html.push_str("</code></pre>");
+    if fence.name.is_some() {
+        html.push_str("</figure>");
+    }
+    html.push('\n');
+    Ok(html)
+}
+
+// Highlights every line on its own, with the colours in `style` attributes.
+fn inline_lines(code: &str, syntax: &SyntaxReference, theme: &Theme) -> Result<Vec<String>> {
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    LinesWithEndings::from(code)
+        .map(|line| {
+            let regions = highlighter.highlight_line(line, &SYNTAXES)?;
+            let html = syntect::html::styled_line_to_highlighted_html(&regions, IncludeBackground::No)?;
+            Ok(strip_line_ending(html))
+        })
+        .collect()
+}
+
+// Highlights every line on its own, with classes for the scopes. Spans carrying
+// on from the line before are opened again, and every span is closed by the end
+// of the line, so each line can be marked on its own.
+fn classed_lines(code: &str, syntax: &SyntaxReference) -> Result<Vec<String>> {
+    let mut parse_state = ParseState::new(syntax);
+    let mut scopes = ScopeStack::new();
+    LinesWithEndings::from(code)
+        .map(|line| {
+            let mut html = String::new();
+            for scope in scopes.as_slice() {
+                let classes: Vec<String> =
+                    scope.build_string().split('.').map(|atom| format!("hl-{}", atom)).collect();
+                html.push_str(&format!("<span class=\"{}\">", classes.join(" ")));
+            }
+            let operations = parse_state.parse_line(line, &SYNTAXES)?;
+            let (spans, _) = line_tokens_to_classed_spans(line, &operations, CLASS_STYLE, &mut scopes)?;
+            html.push_str(&spans);
+            html.push_str(&"</span>".repeat(scopes.len()));
+            Ok(strip_line_ending(html))
+        })
+        .collect()
+}
+
+// The line ending is the only newline in a highlighted line. It comes out again
+// once the line has been wrapped up.
+fn strip_line_ending(mut html: String) -> String {
+    if let Some(index) = html.rfind('\n') {
+        html.remove(index);
+        if html[..index].ends_with('\r') {
+            html.remove(index - 1);
+        }
+    }
+    html
+}
+
+fn gutter_color(theme: &Theme) -> Color {
+    theme.settings.gutter_foreground.or(theme.settings.foreground).unwrap_or(Color::BLACK)
+}
+
+fn mark_color(theme: &Theme) -> Color {
+    theme.settings.line_highlight.unwrap_or(Color { r: 255, g: 255, b: 0, a: 64 })
+}
+
+fn css_color(color: Color) -> String {
+    if color.a == 255 {
+        format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", color.r, color.g, color.b, color.a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Parser, html};
+
+    fn render(markdown: &str, config: &HighlightConfig) -> Result<String> {
+        let events = highlight_code_blocks(markdown, 1, Parser::new(markdown).into_offset_iter(), config)?;
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter());
+        Ok(output)
+    }
+
+    #[test]
+    fn test_parse_fence() {
+        let fence = Fence::parse("rust, linenos, linenostart=10, hl_lines=1 3-5, name=\"src/main.rs\"").unwrap();
+        assert_eq!(
+            fence,
+            Fence {
+                language: Some(String::from("rust")),
+                line_numbers: true,
+                first_line: 10,
+                highlighted_lines: vec![1..=1, 3..=5],
+                name: Some(String::from("src/main.rs")),
+            }
+        );
+        assert_eq!(Fence::parse("").unwrap(), Fence::default());
+
+        assert_eq!(Fence::parse("toml,linenos=false").unwrap(), Fence { language: Some(String::from("toml")), ..Fence::default() });
+        assert!(Fence::parse("rust,linenos=maybe").is_err());
+        assert!(Fence::parse("rust,hl_lines=5-3").is_err());
+        assert!(Fence::parse("rust,hl_lines=x").is_err());
+        assert!(Fence::parse("rust,linenostart=x").is_err());
+    }
+
+    #[test]
+    fn test_inline_styles() {
+        let config = HighlightConfig { enabled: true, ..HighlightConfig::default() };
+        let html = render("Text\n\n```rust\nfn main() {}\n```\n", &config).unwrap();
+
+        assert!(html.starts_with("<p>Text</p>\n<pre class=\"code\" style=\"background-color:#2b303b;color:#c0c5ce;\" \
+             data-lang=\"rust\"><code class=\"language-rust\"><span style=\""), "{}", html);
+        assert!(html.contains("<span style=\"color:#b48ead;\">fn </span>"), "{}", html);
+        assert!(html.ends_with("</span>\n</code></pre>\n"), "{}", html);
+    }
+
+    #[test]
+    fn test_classes() {
+        let config = HighlightConfig { enabled: true, style: HighlightStyle::Classes, ..HighlightConfig::default() };
+        let html = render("```rust,linenos,hl_lines=2,name=lib.rs\n/* a\nb */\nlet x = \"<\";\n```\n", &config).unwrap();
+
+        assert!(html.starts_with("<figure class=\"code-block\"><figcaption>lib.rs</figcaption>\
+             <pre class=\"hl-code\" data-lang=\"rust\"><code class=\"language-rust\">"), "{}", html);
+        // The comment spans two lines, so its span is closed and opened again in between.
+        let lines: Vec<&str> = html.lines().collect();
+        assert!(lines[0].ends_with("<span class=\"hl-line-number\">1</span><span class=\"hl-source hl-rust\">\
+             <span class=\"hl-comment hl-block hl-rust\"><span class=\"hl-punctuation hl-definition hl-comment hl-rust\">/*</span> a\
+             </span></span>"), "{}", html);
+        assert_eq!(
+            lines[1],
+            "<span class=\"hl-line-number\">2</span><mark class=\"hl-mark\"><span class=\"hl-source hl-rust\">\
+             <span class=\"hl-comment hl-block hl-rust\">b <span class=\"hl-punctuation hl-definition hl-comment hl-rust\">*/</span>\
+             </span></span></mark>"
+        );
+        assert!(lines[2].contains("&lt;"), "{}", html);
+        assert_eq!(lines[3], "</code></pre></figure>");
+    }
+
+    #[test]
+    fn test_errors_mention_the_line() {
+        let config = HighlightConfig { enabled: true, ..HighlightConfig::default() };
+        let error = render("# Title\n\n```rust,hl_lines=abc\n```\n", &config).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "in the code block on line 3: `hl_lines` takes line numbers and ranges like `1 3-5`, not `abc`"
+        );
+    }
+
+    #[test]
+    fn test_unknown_attributes_are_ignored() {
+        let config = HighlightConfig { enabled: true, ..HighlightConfig::default() };
+        let html = render("```rust,ignore
+fn main() {}
+```
+", &config).unwrap();
+        assert!(html.contains("data-lang=\"rust\"><code class=\"language-rust\">"), "{}", html);
+        assert!(html.contains("<span style=\"color:#b48ead;\">fn </span>"), "{}", html);
+
+        let fence = Fence::parse("rust,no_run,sparkles,linenos").unwrap();
+        assert_eq!(fence, Fence { language: Some(String::from("rust")), line_numbers: true, ..Fence::default() });
+    }
+
+    #[test]
+    fn test_theme_css() {
+        let css = theme_css("InspiredGitHub").unwrap();
+        assert!(css.contains(".hl-code {"), "{}", css);
+        assert!(css.contains(".hl-mark {"), "{}", css);
+        assert!(theme_css("neon").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use tera::Value;
+
+use crate::config::Config;
+
+/// A version of a page in another language, or in its own.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Translation {
+    pub lang: String,
+    pub title: Option<String>,
+    pub path: String,
+    pub permalink: String,
+}
+
+/// Works out the language of a content file from its path relative to the
+/// content directory, and returns it with the path the file would have in the
+/// default language. `docs/intro.de.md` and `de/docs/intro.md` are both the
+/// German `docs/intro.md`: a language goes either before the extension or at
+/// the top of the content directory.
+pub fn split_language(config: &Config, relative: &Path) -> (String, PathBuf) {
+    let mut components = relative.components();
+    if let Some(Component::Normal(first)) = components.next()
+        && let Some(code) = first.to_str()
+        && config.languages.contains_key(code)
+        && components.clone().next().is_some()
+    {
+        return (code.to_string(), components.as_path().to_path_buf());
+    }
+
+    let file_name = relative.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    match file_language(config, file_name) {
+        Some((stem, code, extension)) => (code.to_string(), relative.with_file_name(format!("{}.{}", stem, extension))),
+        None => (config.language.clone(), relative.to_path_buf()),
+    }
+}
+
+/// Splits a file name like `intro.de.md` into its stem, language and extension,
+/// when it names one of the site's languages.
+pub fn file_language<'a>(config: &Config, file_name: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
+    let (rest, extension) = file_name.rsplit_once('.')?;
+    let (stem, code) = rest.rsplit_once('.')?;
+    (!stem.is_empty() && config.is_language(code)).then_some((stem, code, extension))
+}
+
+/// The strings templates look up with `trans`, read from a `{language}.toml`
+/// file in the i18n directory for each of the site's languages.
+#[derive(Debug, Default, Clone)]
+pub struct Translations {
+    default_language: String,
+    strings: HashMap<String, BTreeMap<String, String>>,
+}
+
+impl Translations {
+    pub fn load(config: &Config) -> Result<Translations> {
+        let mut strings = HashMap::new();
+        for code in config.languages() {
+            let path = Path::new(&config.i18n_dir).join(format!("{}.toml", code));
+            if !path.exists() {
+                continue;
+            }
+            let text = fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+            let table: BTreeMap<String, String> =
+                toml::from_str(&text).with_context(|| format!("invalid translations in {}", path.display()))?;
+            strings.insert(code.to_string(), table);
+        }
+        Ok(Translations { default_language: config.language.clone(), strings })
+    }
+
+    pub fn get(&self, code: &str, key: &str) -> Result<&str> {
+        self.strings
+            .get(code)
+            .and_then(|strings| strings.get(key))
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("there is no translation of `{}` in `{}`", key, code))
+    }
+}
+
+// `trans(key="read_more", lang=lang)`, where `lang` defaults to the site's language.
+impl tera::Function for Translations {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let key = match args.get("key") {
+            Some(Value::String(key)) => key,
+            _ => return Err(tera::Error::msg("`trans` needs a `key` string")),
+        };
+        let code = match args.get("lang") {
+            Some(Value::String(code)) => code,
+            Some(_) => return Err(tera::Error::msg("the `lang` of `trans` has to be a string")),
+            None => &self.default_language,
+        };
+        self.get(code, key).map(Value::from).map_err(|e| tera::Error::msg(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LanguageConfig;
+    use tempfile::tempdir;
+
+    fn config() -> Config {
+        Config {
+            languages: BTreeMap::from([
+                (String::from("de"), LanguageConfig::default()),
+                (String::from("ja"), LanguageConfig::default()),
+            ]),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_split_language() {
+        let config = config();
+        let split = |path: &str| {
+            let (code, path) = split_language(&config, Path::new(path));
+            (code, path.display().to_string())
+        };
+
+        assert_eq!(split("docs/intro.md"), (String::from("en"), String::from("docs/intro.md")));
+        assert_eq!(split("docs/intro.de.md"), (String::from("de"), String::from("docs/intro.md")));
+        assert_eq!(split("docs/intro.en.md"), (String::from("en"), String::from("docs/intro.md")));
+        assert_eq!(split("ja/docs/_index.md"), (String::from("ja"), String::from("docs/_index.md")));
+        assert_eq!(split("docs/_index.ja.md"), (String::from("ja"), String::from("docs/_index.md")));
+        // Dots that aren't before a language are part of the name.
+        assert_eq!(split("releases/v1.2.md"), (String::from("en"), String::from("releases/v1.2.md")));
+        assert_eq!(split("de.md"), (String::from("en"), String::from("de.md")));
+    }
+
+    #[test]
+    fn test_trans() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("en.toml"), "read_more = \"Read more\"").unwrap();
+        fs::write(dir.path().join("de.toml"), "read_more = \"Weiterlesen\"").unwrap();
+        let config = Config { i18n_dir: dir.path().display().to_string(), ..config() };
+
+        let mut tera = tera::Tera::default();
+        tera.register_function("trans", Translations::load(&config).unwrap());
+        let mut render = |template: &str| tera.render_str(template, &tera::Context::new());
+
+        assert_eq!(render("{{ trans(key=\"read_more\") }}").unwrap(), "Read more");
+        assert_eq!(render("{{ trans(key=\"read_more\", lang=\"de\") }}").unwrap(), "Weiterlesen");
+        let error = render("{{ trans(key=\"read_more\", lang=\"ja\") }}").unwrap_err();
+        assert!(format!("{:?}", error).contains("there is no translation of `read_more` in `ja`"), "{:?}", error);
+    }
+
+    #[test]
+    fn test_invalid_translations() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("de.toml"), "read_more = [1, 2]").unwrap();
+        let config = Config { i18n_dir: dir.path().display().to_string(), ..config() };
+
+        let error = Translations::load(&config).unwrap_err();
+        assert!(error.to_string().starts_with("invalid translations in"), "{}", error);
+    }
+}
@@ -0,0 +1,534 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, anyhow, bail};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ExtendedColorType, ImageEncoder, ImageReader};
+use log::info;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use serde::Serialize;
+use tera::Value;
+
+use crate::assets::copy_file_if_changed;
+use crate::config::{Config, ImageFormat, ImagesConfig};
+use crate::slug::slugify;
+
+/// Where processed images are written, relative to the output directory.
+pub const OUTPUT_DIR: &str = "processed_images";
+// Part of every cache key, so that changing how images are made replaces the
+// ones already in the cache.
+const CACHE_VERSION: &[u8] = b"1";
+// How hard the AVIF encoder works, from 1 (slowest, smallest files) to 10.
+const AVIF_SPEED: u8 = 6;
+
+/// The versions of an image made for browsers to pick from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResponsiveImage {
+    /// The widest version in the image's own format, for browsers that don't
+    /// read `srcset`.
+    pub src: String,
+    /// Every width in the image's own format.
+    pub srcset: String,
+    pub sizes: String,
+    /// The size of `src`, for the `width` and `height` attributes.
+    pub width: u32,
+    pub height: u32,
+    /// The versions in the other formats, best first, for `<source>` elements.
+    pub sources: Vec<ImageSource>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageSource {
+    #[serde(rename = "type")]
+    pub mime_type: &'static str,
+    pub srcset: String,
+}
+
+impl ResponsiveImage {
+    /// A `<picture>` offering every format, around an `<img>` in the image's own.
+    pub fn to_html(&self, alt: &str, title: Option<&str>) -> String {
+        let mut html = String::from("<picture>");
+        for source in &self.sources {
+            html.push_str(&format!(
+                "<source type=\"{}\" srcset=\"{}\" sizes=\"{}\">",
+                source.mime_type,
+                escape_attribute(&source.srcset),
+                escape_attribute(&self.sizes)
+            ));
+        }
+        html.push_str(&format!(
+            "<img src=\"{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\" alt=\"{}\"",
+            escape_attribute(&self.src),
+            escape_attribute(&self.srcset),
+            escape_attribute(&self.sizes),
+            self.width,
+            self.height,
+            escape_attribute(alt)
+        ));
+        if let Some(title) = title.filter(|title| !title.is_empty()) {
+            html.push_str(&format!(" title=\"{}\"", escape_attribute(title)));
+        }
+        html.push_str("></picture>");
+        html
+    }
+}
+
+/// Resizes and converts images. What it makes is kept in the cache directory,
+/// named after a hash of the source image and the settings, so later builds
+/// only copy it to the output directory.
+#[derive(Debug, Default)]
+pub struct ImageProcessor {
+    config: ImagesConfig,
+    output_dir: PathBuf,
+    // Where the output directory's `processed_images` is on the site, built
+    // from `base_url` like page permalinks so a site under `/blog/` works.
+    url: String,
+    // The images done in this run, keyed by file, size and modification time,
+    // so an image on several pages is only looked at once, and an edited one
+    // is looked at again.
+    processed: Mutex<HashMap<SourceKey, Arc<Processed>>>,
+}
+
+type SourceKey = (PathBuf, u64, Option<SystemTime>);
+// Filled in by whichever thread gets to the image first, while the others wait.
+type Processed = OnceLock<Result<ResponsiveImage, String>>;
+
+impl ImageProcessor {
+    pub fn new(config: &ImagesConfig, output_dir: &Path, base_url: &str) -> ImageProcessor {
+        ImageProcessor {
+            config: config.clone(),
+            output_dir: output_dir.to_path_buf(),
+            url: format!("{}/{}", base_url.trim_end_matches('/'), OUTPUT_DIR),
+            processed: Mutex::default(),
+        }
+    }
+
+    pub fn process(&self, source: &Path) -> Result<ResponsiveImage> {
+        let metadata = fs::metadata(source).with_context(|| format!("failed to read {}", source.display()))?;
+        let key = (source.to_path_buf(), metadata.len(), metadata.modified().ok());
+        let done = Arc::clone(self.processed.lock().unwrap().entry(key).or_default());
+        done.get_or_init(|| self.process_uncached(source).map_err(|e| format!("{:#}", e)))
+            .clone()
+            .map_err(|e| anyhow!(e))
+    }
+
+    fn process_uncached(&self, source: &Path) -> Result<ResponsiveImage> {
+        let own_format = match image::ImageFormat::from_path(source) {
+            Ok(image::ImageFormat::Jpeg) => Encoding::Jpeg,
+            Ok(image::ImageFormat::Png) => Encoding::Png,
+            Ok(image::ImageFormat::WebP) => Encoding::Webp,
+            _ => bail!("{} isn't a JPEG, PNG or WebP image", source.display()),
+        };
+        let bytes = fs::read(source).with_context(|| format!("failed to read {}", source.display()))?;
+        let (width, height) = ImageReader::new(Cursor::new(&bytes))
+            .with_guessed_format()?
+            .into_dimensions()
+            .with_context(|| format!("failed to read {}", source.display()))?;
+
+        let quality = [self.config.quality];
+        let hash = fnv1a(&[CACHE_VERSION, &quality, &bytes]);
+        let stem = slugify(&source.file_stem().unwrap_or_default().to_string_lossy());
+        let stem = if stem.is_empty() { String::from("image") } else { stem };
+
+        let mut encodings = vec![own_format];
+        encodings.extend(
+            self.config.formats.iter().map(|format| Encoding::from(*format)).filter(|encoding| *encoding != own_format),
+        );
+        let widths = widths(&self.config.widths, width);
+
+        // Decoding a large photo takes a while, so it only happens when something
+        // isn't in the cache.
+        let mut decoded: Option<DynamicImage> = None;
+        let mut made = 0;
+        let mut srcsets = Vec::new();
+        for encoding in &encodings {
+            let mut srcset = Vec::new();
+            for &resized_width in &widths {
+                let name = format!("{}-{:016x}-{}.{}", stem, hash, resized_width, encoding.extension());
+                let cached = Path::new(&self.config.cache_dir).join(&name);
+                if !cached.exists() {
+                    let image = match decoded.take() {
+                        Some(image) => image,
+                        None => image::load_from_memory(&bytes)
+                            .with_context(|| format!("failed to decode {}", source.display()))?,
+                    };
+                    let resized = resize(&image, resized_width, scaled_height(width, height, resized_width));
+                    let encoded = encode(&resized, *encoding, self.config.quality)
+                        .with_context(|| format!("failed to convert {}", source.display()))?;
+                    write_atomically(&cached, &encoded)?;
+                    decoded = Some(image);
+                    made += 1;
+                }
+                copy_file_if_changed(&cached, &self.output_dir.join(OUTPUT_DIR).join(&name))?;
+                srcset.push(format!("{}/{} {}w", self.url, name, resized_width));
+            }
+            srcsets.push((*encoding, srcset.join(", ")));
+        }
+        if made > 0 {
+            info!("Made {} versions of {}", made, source.display());
+        }
+
+        let widest = widths.last().copied().unwrap_or(width);
+        let (_, own_srcset) = srcsets.remove(0);
+        Ok(ResponsiveImage {
+            src: format!("{}/{}-{:016x}-{}.{}", self.url, stem, hash, widest, own_format.extension()),
+            srcset: own_srcset,
+            sizes: self.config.sizes.clone(),
+            width: widest,
+            height: scaled_height(width, height, widest),
+            sources: srcsets
+                .into_iter()
+                .map(|(encoding, srcset)| ImageSource { mime_type: encoding.mime_type(), srcset })
+                .collect(),
+        })
+    }
+}
+
+/// `resize_image(path="blog/cat.jpg")` for templates, with `path` relative to
+/// the content directory or, failing that, the static directory.
+pub struct ResizeImage {
+    processor: Arc<ImageProcessor>,
+    dirs: [PathBuf; 2],
+}
+
+impl ResizeImage {
+    pub fn new(processor: Arc<ImageProcessor>, config: &Config) -> ResizeImage {
+        ResizeImage { processor, dirs: [PathBuf::from(&config.content_dir), PathBuf::from(&config.static_dir)] }
+    }
+}
+
+impl tera::Function for ResizeImage {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let Some(Value::String(path)) = args.get("path") else {
+            return Err(tera::Error::msg("`resize_image` needs a `path` string"));
+        };
+        let path = path.trim_start_matches('/');
+        let source = self
+            .dirs
+            .iter()
+            .map(|dir| dir.join(path))
+            .find(|source| source.is_file())
+            .ok_or_else(|| tera::Error::msg(format!("there is no image `{}` in the content or static directory", path)))?;
+        let image = self.processor.process(&source).map_err(|e| tera::Error::msg(format!("{:#}", e)))?;
+        tera::to_value(image).map_err(tera::Error::from)
+    }
+}
+
+/// Replaces every Markdown image `process` makes a [`ResponsiveImage`] of with
+/// a `<picture>`. Images it returns `None` for, like ones on other sites, are
+/// left as they are. `process` gets the image's URL and where it is in the
+/// Markdown.
+pub fn replace_images<'a>(
+    events: Vec<(Event<'a>, Range<usize>)>,
+    mut process: impl FnMut(&str, Range<usize>) -> Result<Option<ResponsiveImage>>,
+) -> Result<Vec<(Event<'a>, Range<usize>)>> {
+    let mut output = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    while let Some((event, range)) = events.next() {
+        let Event::Start(Tag::Image { dest_url, title, .. }) = &event else {
+            output.push((event, range));
+            continue;
+        };
+        let Some(image) = process(dest_url, range.clone())? else {
+            output.push((event, range));
+            continue;
+        };
+
+        // The alt text is whatever text is inside the image.
+        let mut alt = String::new();
+        for (event, _) in events.by_ref() {
+            match event {
+                Event::End(TagEnd::Image) => break,
+                Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+                _ => {},
+            }
+        }
+        let html = image.to_html(&alt, Some(title));
+        output.push((Event::InlineHtml(CowStr::from(html)), range));
+    }
+    Ok(output)
+}
+
+/// Whether `url` could be an image [`ResponsiveImage`]s are made of: a JPEG, PNG
+/// or WebP file in the site rather than on another one.
+pub fn is_local_image(url: &str) -> bool {
+    let has_scheme = url.split_once(':').is_some_and(|(scheme, _)| !scheme.contains('/'));
+    let extension = url.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    !has_scheme && !url.starts_with("//") && matches!(extension.as_deref(), Some("jpg" | "jpeg" | "png" | "webp"))
+}
+
+// The formats images are written in: their own, and the ones in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl From<ImageFormat> for Encoding {
+    fn from(format: ImageFormat) -> Encoding {
+        match format {
+            ImageFormat::Avif => Encoding::Avif,
+            ImageFormat::Webp => Encoding::Webp,
+        }
+    }
+}
+
+impl Encoding {
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Jpeg => "jpg",
+            Encoding::Png => "png",
+            Encoding::Webp => "webp",
+            Encoding::Avif => "avif",
+        }
+    }
+
+    fn mime_type(self) -> &'static str {
+        match self {
+            Encoding::Jpeg => "image/jpeg",
+            Encoding::Png => "image/png",
+            Encoding::Webp => "image/webp",
+            Encoding::Avif => "image/avif",
+        }
+    }
+}
+
+// The configured widths narrower than the image, along with the image's own
+// width when any of them would have made it wider.
+fn widths(configured: &[u32], width: u32) -> Vec<u32> {
+    let mut widths: Vec<u32> = configured.iter().copied().filter(|configured| *configured < width).collect();
+    if widths.len() < configured.len() || widths.is_empty() {
+        widths.push(width);
+    }
+    widths.sort_unstable();
+    widths.dedup();
+    widths
+}
+
+fn scaled_height(width: u32, height: u32, scaled_width: u32) -> u32 {
+    ((u64::from(height) * u64::from(scaled_width) + u64::from(width) / 2) / u64::from(width.max(1))).max(1) as u32
+}
+
+fn resize(image: &DynamicImage, width: u32, height: u32) -> Cow<'_, DynamicImage> {
+    if width == image.width() && height == image.height() {
+        Cow::Borrowed(image)
+    } else {
+        Cow::Owned(image.resize_exact(width, height, FilterType::Lanczos3))
+    }
+}
+
+fn encode(image: &DynamicImage, encoding: Encoding, quality: u8) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match encoding {
+        // JPEG has no transparency.
+        Encoding::Jpeg => JpegEncoder::new_with_quality(&mut bytes, quality).encode_image(&image.to_rgb8())?,
+        Encoding::Png => image.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)?,
+        Encoding::Webp => {
+            let rgba = image.to_rgba8();
+            let webp = webp::Encoder::from_rgba(&rgba, image.width(), image.height())
+                .encode_simple(false, f32::from(quality))
+                .map_err(|e| anyhow!("failed to encode WebP: {:?}", e))?;
+            bytes.extend_from_slice(&webp);
+        },
+        Encoding::Avif => {
+            let rgba = image.to_rgba8();
+            AvifEncoder::new_with_speed_quality(&mut bytes, AVIF_SPEED, quality).write_image(
+                &rgba,
+                image.width(),
+                image.height(),
+                ExtendedColorType::Rgba8,
+            )?;
+        },
+    }
+    Ok(bytes)
+}
+
+// Writes to a temporary file first, so a build that is stopped halfway doesn't
+// leave half an image in the cache.
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temporary, bytes).with_context(|| format!("failed to write {}", temporary.display()))?;
+    fs::rename(&temporary, path).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+// 64-bit FNV-1a. It isn't meant to stand up to attacks, but unlike
+// `DefaultHasher` it gives the same hash on every Rust version, which the
+// names of cached images rely on.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in *part {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        // Keeps `["ab", "c"]` and `["a", "bc"]` apart.
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn escape_attribute(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Parser, html};
+    use tempfile::tempdir;
+
+    fn write_png(path: &Path, width: u32, height: u32) {
+        image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 7) as u8, (y * 5) as u8, 90])).save(path).unwrap();
+    }
+
+    fn processor(dir: &Path, formats: Vec<ImageFormat>) -> ImageProcessor {
+        let config = ImagesConfig {
+            widths: vec![16, 32, 64],
+            formats,
+            cache_dir: dir.join("cache").display().to_string(),
+            ..ImagesConfig::default()
+        };
+        ImageProcessor::new(&config, &dir.join("output"), "")
+    }
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(widths(&[480, 960, 1600], 6000), vec![480, 960, 1600]);
+        assert_eq!(widths(&[480, 960, 1600], 800), vec![480, 800]);
+        assert_eq!(widths(&[480, 960], 300), vec![300]);
+        assert_eq!(widths(&[], 300), vec![300]);
+        assert_eq!(scaled_height(4000, 3000, 480), 360);
+        assert_eq!(scaled_height(3, 1000, 1), 333);
+    }
+
+    #[test]
+    fn test_process() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("My Cat.png");
+        write_png(&source, 40, 30);
+
+        let image = processor(dir.path(), vec![ImageFormat::Avif, ImageFormat::Webp]).process(&source).unwrap();
+
+        let hash = fnv1a(&[CACHE_VERSION, &[80], &fs::read(&source).unwrap()]);
+        let name = |width: u32, extension: &str| format!("/processed_images/my-cat-{:016x}-{}.{}", hash, width, extension);
+        assert_eq!(image.src, name(40, "png"));
+        assert_eq!(image.srcset, format!("{} 16w, {} 32w, {} 40w", name(16, "png"), name(32, "png"), name(40, "png")));
+        assert_eq!((image.width, image.height), (40, 30));
+        assert_eq!(image.sizes, "100vw");
+        let types: Vec<&str> = image.sources.iter().map(|source| source.mime_type).collect();
+        assert_eq!(types, vec!["image/avif", "image/webp"]);
+        assert!(image.sources[1].srcset.ends_with(&format!("{} 40w", name(40, "webp"))));
+
+        let resized = image::open(dir.path().join("output").join(&name(16, "webp")[1..])).unwrap();
+        assert_eq!((resized.width(), resized.height()), (16, 12));
+        assert!(dir.path().join("output").join(&name(32, "avif")[1..]).exists());
+        assert_eq!(fs::read_dir(dir.path().join("cache")).unwrap().count(), 9);
+    }
+
+    #[test]
+    fn test_process_webp() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("cat.webp");
+        image::RgbaImage::new(40, 30).save(&source).unwrap();
+
+        let image = processor(dir.path(), vec![ImageFormat::Avif, ImageFormat::Webp]).process(&source).unwrap();
+
+        // WebP is the image's own format, so it isn't offered a second time.
+        assert!(image.src.ends_with("-40.webp"), "{}", image.src);
+        let types: Vec<&str> = image.sources.iter().map(|source| source.mime_type).collect();
+        assert_eq!(types, vec!["image/avif"]);
+        assert_eq!(fs::read_dir(dir.path().join("cache")).unwrap().count(), 6);
+    }
+
+    #[test]
+    fn test_process_uses_the_cache() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("cat.png");
+        write_png(&source, 40, 30);
+        let first = processor(dir.path(), vec![ImageFormat::Webp]).process(&source).unwrap();
+
+        // Whatever is in the cache is used as it is, without making it again.
+        let cached = dir.path().join("cache").join(first.src.trim_start_matches("/processed_images/"));
+        fs::write(&cached, "cached").unwrap();
+        fs::remove_dir_all(dir.path().join("output")).unwrap();
+        let second = processor(dir.path(), vec![ImageFormat::Webp]).process(&source).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(fs::read_to_string(dir.path().join("output").join(&first.src[1..])).unwrap(), "cached");
+
+        // A different image gets different names.
+        write_png(&source, 40, 20);
+        let third = processor(dir.path(), vec![ImageFormat::Webp]).process(&source).unwrap();
+        assert_ne!(first.src, third.src);
+        assert_eq!(third.height, 20);
+    }
+
+    #[test]
+    fn test_process_errors() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("notes.txt"), "hi").unwrap();
+        fs::write(dir.path().join("broken.jpg"), "not a jpeg").unwrap();
+        let processor = processor(dir.path(), Vec::new());
+
+        assert!(processor.process(&dir.path().join("missing.png")).is_err());
+        let error = processor.process(&dir.path().join("notes.txt")).unwrap_err();
+        assert!(error.to_string().ends_with("isn't a JPEG, PNG or WebP image"), "{}", error);
+        assert!(processor.process(&dir.path().join("broken.jpg")).is_err());
+    }
+
+    #[test]
+    fn test_replace_images() {
+        let image = ResponsiveImage {
+            src: String::from("/processed_images/cat-960.jpg"),
+            srcset: String::from("/processed_images/cat-480.jpg 480w, /processed_images/cat-960.jpg 960w"),
+            sizes: String::from("100vw"),
+            width: 960,
+            height: 640,
+            sources: vec![ImageSource { mime_type: "image/webp", srcset: String::from("/processed_images/cat-480.webp 480w") }],
+        };
+        let markdown = "![A *cat* & \"dog\"](cat.jpg \"Pets\") ![logo](https://example.com/logo.png)";
+        let events = Parser::new(markdown).into_offset_iter().collect();
+
+        let events = replace_images(events, |url, range| {
+            assert_eq!(&markdown[range][..2], "![");
+            Ok(is_local_image(url).then(|| image.clone()))
+        })
+        .unwrap();
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter().map(|(event, _)| event));
+
+        assert_eq!(
+            output,
+            "<p><picture><source type=\"image/webp\" srcset=\"/processed_images/cat-480.webp 480w\" sizes=\"100vw\">\
+             <img src=\"/processed_images/cat-960.jpg\" \
+             srcset=\"/processed_images/cat-480.jpg 480w, /processed_images/cat-960.jpg 960w\" sizes=\"100vw\" \
+             width=\"960\" height=\"640\" alt=\"A cat &amp; &quot;dog&quot;\" title=\"Pets\"></picture> \
+             <img src=\"https://example.com/logo.png\" alt=\"logo\" /></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_is_local_image() {
+        assert!(is_local_image("cat.jpg"));
+        assert!(is_local_image("/images/Cat.PNG"));
+        assert!(!is_local_image("https://example.com/cat.jpg"));
+        assert!(!is_local_image("//example.com/cat.jpg"));
+        assert!(!is_local_image("diagram.svg"));
+        assert!(!is_local_image("data:image/png;base64,xyz"));
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, anyhow, bail};
+use pulldown_cmark::{CowStr, Event, Tag};
+
+/// A page or section that Markdown links can point at by its source file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkTarget {
+    pub permalink: String,
+    /// The ids of the headings on the page.
+    pub anchors: HashSet<String>,
+}
+
+/// Link targets keyed by source file relative to the content directory, e.g.
+/// `docs/intro.md` or `blog/_index.md`.
+pub type LinkTargets = HashMap<String, LinkTarget>;
+
+/// Rewrites a link to a Markdown file into a link to the page made from it.
+/// `@/docs/intro.md#setup` is relative to the content directory, and links
+/// like `../intro.md` to `current`, the file the link is in. Links to files that
+/// aren't pages, or to headings those pages don't have, are errors. Every other
+/// event is passed through untouched.
+pub fn resolve_link<'a>(event: Event<'a>, current: &str, targets: &LinkTargets) -> Result<Event<'a>> {
+    let Event::Start(Tag::Link { link_type, dest_url, title, id }) = event else {
+        return Ok(event);
+    };
+    let Some((path, fragment)) = source_path(&dest_url, current) else {
+        return Ok(Event::Start(Tag::Link { link_type, dest_url, title, id }));
+    };
+
+    let target = targets
+        .get(&path)
+        .ok_or_else(|| anyhow!("broken link to `{}`, there is no page {}", dest_url, path))?;
+    let url = match fragment {
+        Some(fragment) if !target.anchors.contains(fragment) => {
+            bail!("broken link to `{}`, {} has no heading with the id `{}`", dest_url, path, fragment)
+        },
+        Some(fragment) => format!("{}#{}", target.permalink, fragment),
+        None => target.permalink.clone(),
+    };
+    Ok(Event::Start(Tag::Link { link_type, dest_url: CowStr::from(url), title, id }))
+}
+
+// Works out which source file a link points at, relative to the content
+// directory, along with the heading it points at. Links that aren't to a
+// Markdown file get nothing.
+fn source_path<'a>(url: &'a str, current: &str) -> Option<(String, Option<&'a str>)> {
+    let (path, fragment) = match url.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (url, None),
+    };
+
+    if let Some(path) = path.strip_prefix("@/") {
+        return Some((normalize(path), fragment));
+    }
+    // Absolute URLs and links with a scheme (`https:`, `mailto:`) are left alone.
+    if path.starts_with('/') || path.contains(':') || !path.ends_with(".md") {
+        return None;
+    }
+    let directory = current.rsplit_once('/').map(|(directory, _)| directory).unwrap_or_default();
+    Some((normalize(&format!("{}/{}", directory, path)), fragment))
+}
+
+// Resolves the `.` and `..` in a relative path.
+fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => {
+                components.pop();
+            },
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Parser, html};
+
+    fn targets() -> LinkTargets {
+        HashMap::from([
+            (
+                String::from("docs/intro.md"),
+                LinkTarget {
+                    permalink: String::from("https://example.com/docs/intro/"),
+                    anchors: HashSet::from([String::from("setup")]),
+                },
+            ),
+            (
+                String::from("blog/_index.md"),
+                LinkTarget { permalink: String::from("https://example.com/blog/"), anchors: HashSet::new() },
+            ),
+        ])
+    }
+
+    fn render(markdown: &str, current: &str) -> Result<String> {
+        let targets = targets();
+        let events = Parser::new(markdown)
+            .map(|event| resolve_link(event, current, &targets))
+            .collect::<Result<Vec<_>>>()?;
+        let mut output = String::new();
+        html::push_html(&mut output, events.into_iter());
+        Ok(output)
+    }
+
+    #[test]
+    fn test_resolve_links() {
+        assert_eq!(
+            render("[a](@/docs/intro.md#setup) [b](../blog/_index.md) [c](./intro.md)", "docs/guide.md").unwrap(),
+            "<p><a href=\"https://example.com/docs/intro/#setup\">a</a> <a href=\"https://example.com/blog/\">b</a> \
+             <a href=\"https://example.com/docs/intro/\">c</a></p>\n"
+        );
+        // Links that aren't to Markdown files stay as they are.
+        assert_eq!(
+            render("[a](https://example.com/x.md) [b](/intro.md) [c](#top) [d](cat.png)", "docs/guide.md").unwrap(),
+            "<p><a href=\"https://example.com/x.md\">a</a> <a href=\"/intro.md\">b</a> <a href=\"#top\">c</a> \
+             <a href=\"cat.png\">d</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_broken_links() {
+        let error = render("[a](@/docs/outro.md)", "index.md").unwrap_err();
+        assert_eq!(error.to_string(), "broken link to `@/docs/outro.md`, there is no page docs/outro.md");
+
+        let error = render("[a](intro.md#install)", "docs/guide.md").unwrap_err();
+        assert_eq!(error.to_string(), "broken link to `intro.md#install`, docs/intro.md has no heading with the id `install`");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("docs/./a/../intro.md"), "docs/intro.md");
+        assert_eq!(normalize("/intro.md"), "intro.md");
+    }
+}
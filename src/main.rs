@@ -1,35 +1,202 @@
+mod assets;
+mod check;
+mod config;
+mod date;
+mod error;
+mod feed;
+mod front_matter;
+mod headings;
+mod highlight;
+mod i18n;
+mod images;
+mod links;
+mod page;
+mod pagination;
+mod sass;
+mod section;
+mod serve;
+mod shortcodes;
+mod sitemap;
+mod slug;
+mod taxonomy;
+mod templates;
+mod watch;
+
+use std::collections::HashMap;
 use std::fs;
 use std::ffi::OsStr;
 use std::io::{self, Write};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
-use clap::{Parser as ClapParser};
-use log::{info};
+use anyhow::{Context as _, Result, anyhow, bail};
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::{Parser as ClapParser, Subcommand, ValueEnum};
+use log::{error, info, warn};
 use env_logger::Env;
-use pulldown_cmark::{Parser, Options, html};
+use pulldown_cmark::{Parser, html};
+use rayon::prelude::*;
 use tera::{Tera, Context};
 use walkdir::WalkDir;
 
+use config::{Config, DEFAULT_CONFIG_FILE};
+use error::{BuildError, BuildErrors, ErrorCollector, Stage};
+use feed::{Feed, FeedEntry};
+use front_matter::{parse_front_matter, split_front_matter};
+use headings::TocEntry;
+use images::ImageProcessor;
+use links::{LinkTarget, LinkTargets};
+use page::{Exclusion, Page, url_components};
+use pagination::Paginator;
+use section::{Section, SectionMeta};
+use serve::LiveReload;
+use sitemap::SitemapEntry;
+use taxonomy::{Taxonomy, Term};
+use templates::{find_section_template, find_taxonomy_template, find_template};
+
 #[derive(ClapParser)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Sets a custom config file
-    #[arg(short, long, value_name = "FILE")]
+    #[arg(short, long, value_name = "FILE", global = true)]
     content: Option<String>,
 
     /// Sets a custom config file
-    #[arg(short, long, value_name = "FILE")]
+    #[arg(short, long, value_name = "FILE", global = true)]
     output: Option<String>,
+
+    /// Sets the site config file (defaults to rusty_ssg.toml)
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<String>,
+
+    /// Sets how many pages are rendered at once (defaults to one per CPU core)
+    #[arg(short, long, value_name = "N", global = true)]
+    jobs: Option<usize>,
+
+    /// Stops the build at the first error instead of reporting them all
+    #[arg(long, global = true)]
+    fail_fast: bool,
+
+    /// Builds the pages marked `draft`
+    #[arg(long, global = true)]
+    drafts: bool,
+
+    /// Builds the pages whose `publish_date` hasn't come yet or whose
+    /// `expiry_date` has passed
+    #[arg(long, global = true)]
+    future: bool,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+enum Command {
+    /// Builds the site once (the default)
+    Build,
+    /// Builds the site, then rebuilds it whenever the content, templates, static
+    /// files or config change
+    Watch,
+    /// Builds and watches the site like `watch`, and serves it over HTTP with
+    /// pages reloading themselves after every rebuild
+    Serve {
+        /// The port to listen on
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+
+        /// The address to listen on
+        #[arg(short, long, default_value = "127.0.0.1")]
+        interface: String,
+    },
+    /// Prints the stylesheet for code highlighted with `style = "classes"`, or
+    /// writes it to FILE
+    ThemeCss {
+        /// The theme to use (defaults to the one in the config)
+        #[arg(long)]
+        theme: Option<String>,
+
+        /// Where to write the stylesheet
+        file: Option<PathBuf>,
+    },
+    /// Checks the links in the built site: every page and file linked to has
+    /// to exist, along with the `#fragment` ids on them. Links to other sites
+    /// are listed but not fetched
+    Check {
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t = ReportFormat::Human)]
+        format: ReportFormat,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ReportFormat {
+    Human,
+    Json,
 }
 
 struct SitePaths {
     content_path: String,
     template_path: String,
     output_path: String,
+    static_path: String,
     base_template: String,
 }
 
-fn main() {
+// Everything a build needs to know about the site.
+struct Site {
+    paths: SitePaths,
+    config: Config,
+    // Set when serving the site, so pages get the live reload script.
+    live_reload: bool,
+    // Stop the build at the first error rather than collecting them all.
+    fail_fast: bool,
+    // Build drafts, and pages outside their `publish_date` and `expiry_date`.
+    drafts: bool,
+    future: bool,
+    // The templates are parsed once and shared by every page in the build.
+    tera: Tera,
+    // Shared with the `resize_image` template function.
+    images: Arc<ImageProcessor>,
+}
+
+impl Site {
+    fn new(config: Config) -> Result<Site> {
+        let paths = SitePaths {
+            content_path: config.content_dir.clone(),
+            template_path: format!("{}/**/*.{{html,txt}}", config.template_dir.trim_end_matches('/')),
+            output_path: config.output_dir.clone(),
+            static_path: config.static_dir.clone(),
+            base_template: String::from("base.html"),
+        };
+
+        let images = Arc::new(ImageProcessor::new(&config.images, Path::new(&config.output_dir), &config.base_url));
+        let mut tera = load_templates(&paths.template_path)?;
+        register_functions(&mut tera, &config, &images)?;
+
+        Ok(Site { paths, config, live_reload: false, fail_fast: false, drafts: false, future: false, tera, images })
+    }
+}
+
+// The functions templates get on top of Tera's own.
+fn register_functions(tera: &mut Tera, config: &Config, images: &Arc<ImageProcessor>) -> Result<()> {
+    tera.register_function("trans", i18n::Translations::load(config)?);
+    tera.register_function("resize_image", images::ResizeImage::new(Arc::clone(images), config));
+    Ok(())
+}
+
+fn load_templates(template_path: &str) -> Result<Tera> {
+    let started = Instant::now();
+    let tera = Tera::new(template_path)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("failed to load the templates in {}", template_path))?;
+    info!("Parsed {} templates in {:.2?}", tera.get_template_names().count(), started.elapsed());
+
+    Ok(tera)
+}
+
+fn main() -> Result<()> {
     // Initialize the logger based on the `RUST_LOG` environment variable.
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
@@ -37,82 +204,984 @@ fn main() {
 
     let cli = Cli::parse();
 
-    let site_paths = SitePaths {
-        content_path: cli.content.unwrap_or_else(|| String::from("./content")),
-        template_path: String::from("./templates/*.html"),
-        output_path: cli.output.unwrap_or_else(|| String::from("./output")),
-        base_template: String::from("base.html"),
-    };
+    // Zero threads tells rayon to use one per CPU core.
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.jobs.unwrap_or(0))
+        .build_global()
+        .context("failed to start the worker threads")?;
 
-    // Convert the files in content with the template files and put them in the output directory.
-    convert_files(&site_paths);
+    match &cli.command {
+        None | Some(Command::Build) => {
+            // Convert the files in content with the template files and put them in the output directory.
+            Ok(convert_files(&load_site(&cli)?)?)
+        },
+        Some(Command::Watch) => watch_site(&cli, |_| {}),
+        Some(Command::Serve { port, interface }) => {
+            let live_reload = Arc::new(LiveReload::default());
+            let address = format!("{}:{}", interface, port);
+            serve::serve(PathBuf::from(&watched_config(&cli).output_dir), &address, Arc::clone(&live_reload))?;
+
+            watch_site(&cli, |result| match result {
+                Ok(()) => live_reload.build_succeeded(),
+                Err(e) => live_reload.build_failed(e),
+            })
+        },
+        Some(Command::ThemeCss { theme, file }) => write_theme_css(&cli, theme.as_deref(), file.as_deref()),
+        Some(Command::Check { format }) => check_links(&cli, *format),
+    }
+}
+
+// Checks the output directory as it is; it doesn't build the site first.
+fn check_links(cli: &Cli, format: ReportFormat) -> Result<()> {
+    let config = load_config(cli)?;
+    let report = check::check_output(Path::new(&config.output_dir), &config.base_url)?;
+    match format {
+        ReportFormat::Human => print!("{}", report.to_human()),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+
+    // A failing exit code lets CI stop on broken links.
+    if report.broken_count() > 0 {
+        bail!("found {} broken links", report.broken_count());
+    }
+    Ok(())
+}
+
+// The stylesheet only needs the config, not the content or the templates.
+fn write_theme_css(cli: &Cli, theme: Option<&str>, file: Option<&Path>) -> Result<()> {
+    let config = load_config(cli)?;
+    let css = highlight::theme_css(theme.unwrap_or(&config.highlighting.theme))?;
+    match file {
+        Some(file) => {
+            create_and_write_file(file, &css).with_context(|| format!("failed to write {}", file.display()))?;
+            info!("Wrote the highlighting stylesheet to {}", file.display());
+        },
+        None => print!("{}", css),
+    }
+    Ok(())
+}
+
+fn load_site(cli: &Cli) -> Result<Site> {
+    let mut site = Site::new(load_config(cli)?)?;
+    site.live_reload = matches!(cli.command, Some(Command::Serve { .. }));
+    site.fail_fast = cli.fail_fast;
+    site.drafts = cli.drafts;
+    site.future = cli.future;
+    Ok(site)
+}
+
+fn config_path(cli: &Cli) -> &str {
+    cli.config.as_deref().unwrap_or(DEFAULT_CONFIG_FILE)
+}
+
+// Reads the config file and lets any flags given on the command line override it.
+fn load_config(cli: &Cli) -> Result<Config> {
+    Ok(override_config(cli, Config::load(Path::new(config_path(cli)), cli.config.is_some())?))
+}
+
+fn override_config(cli: &Cli, mut config: Config) -> Config {
+    if let Some(content) = &cli.content {
+        config.content_dir = content.clone();
+    }
+    if let Some(output) = &cli.output {
+        config.output_dir = output.clone();
+    }
+
+    // Links have to point at the development server rather than the real site.
+    // A browser can't open `0.0.0.0`, so listening everywhere links to localhost.
+    if let Some(Command::Serve { port, interface }) = &cli.command {
+        let host = match interface.parse::<IpAddr>() {
+            Ok(address) if address.is_unspecified() => String::from("localhost"),
+            Ok(IpAddr::V6(address)) => format!("[{}]", address),
+            _ => interface.clone(),
+        };
+        config.base_url = format!("http://{}:{}", host, port);
+    }
+
+    config
+}
+
+// The config that says which directories to watch and serve. A broken config
+// file is reported by the first build, so until it's fixed the defaults are used.
+fn watched_config(cli: &Cli) -> Config {
+    load_config(cli).unwrap_or_else(|_| override_config(cli, Config::default()))
+}
+
+fn convert_files(site: &Site) -> Result<(), BuildErrors> {
+    let mut errors = ErrorCollector::new(site.fail_fast);
+    let content = load_content(site, &mut errors)?;
+
+    par_try_map(&content.pages, &mut errors, |page| {
+        info!("Processing: {}", page.source.display());
+        convert_file_to_html(site, &content, page)
+    })?;
+    let listings = listings(site, &content);
+    par_try_map(&listings, &mut errors, |listing| convert_listing_to_html(site, &content, listing))?;
+    if site.config.base_url.is_empty() {
+        warn!("The sitemap and feeds need absolute URLs but `base_url` isn't set, their links will be relative");
+    }
+    par_try_map(&feeds(site, &content), &mut errors, |feed| write_feed(site, &content, feed))?;
+    errors.check(write_sitemap(site, &content))?;
+
+    // A directory holding an `index.md` is a page bundle; its other files are
+    // copied into whatever directory that page was written to.
+    // Translations of a bundle (`index.md` and `index.de.md`) share its files,
+    // which go with the page in the default language.
+    let mut bundles: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for page in content.pages.iter().filter(|page| page.is_index()) {
+        if let (Some(bundle_dir), Some(page_dir)) = (page.source.parent(), page.output_file.parent())
+            && (page.lang == site.config.language || !bundles.contains_key(bundle_dir))
+        {
+            bundles.insert(bundle_dir.to_path_buf(), page_dir.to_path_buf());
+        }
+    }
+    let output_path = Path::new(&site.paths.output_path);
+    let content_path = Path::new(&site.paths.content_path);
+    errors.check(
+        assets::copy_page_resources(&content.resources, content_path, output_path, &bundles)
+            .map_err(|e| BuildError::new(Stage::Write, content_path, e)),
+    )?;
+    let static_path = Path::new(&site.paths.static_path);
+    errors.check(
+        assets::copy_dir(static_path, output_path).map_err(|e| BuildError::new(Stage::Write, static_path, e)),
+    )?;
+    compile_sass(site, &mut errors)?;
+
+    errors.finish()
 }
 
-fn convert_files(site_paths: &SitePaths) {
-    for entry in WalkDir::new(&site_paths.content_path)
+// Stylesheets are compiled after the static files are copied, so a compiled
+// `style.css` wins over one in the static directory.
+fn compile_sass(site: &Site, errors: &mut ErrorCollector) -> Result<(), BuildErrors> {
+    let sass_dir = Path::new(&site.config.sass_dir);
+    let output_path = Path::new(&site.paths.output_path);
+    par_try_map(&sass::stylesheets(sass_dir), errors, |source| {
+        sass::compile(source, sass_dir, output_path, &site.config.sass)
+    })?;
+    Ok(())
+}
+
+// Everything read from the content directory.
+#[derive(Default)]
+struct Content {
+    pages: Vec<Page>,
+    sections: Vec<Section>,
+    taxonomies: Vec<Taxonomy>,
+    // What links to Markdown files turn into.
+    links: LinkTargets,
+    // The files that aren't Markdown, copied over as they are.
+    resources: Vec<PathBuf>,
+}
+
+fn load_content(site: &Site, errors: &mut ErrorCollector) -> Result<Content, BuildErrors> {
+    let (md_files, resources): (Vec<PathBuf>, Vec<PathBuf>) = WalkDir::new(&site.paths.content_path)
+        .sort_by_file_name()
         .into_iter()
         .filter_map(|e| e.ok()) // Ignore any errors during traversal
-        .filter(|e| {
-            // First, check if the entry is a file
-            e.file_type().is_file() &&
-            // Then, check if the file has the ".md" extension
-            e.path().extension().and_then(OsStr::to_str) == Some("md")
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        // Markdown files become pages, everything else is copied next to them.
+        .partition(|path| path.extension().and_then(OsStr::to_str) == Some("md"));
+    let (section_files, md_files): (Vec<PathBuf>, Vec<PathBuf>) = md_files.into_iter().partition(|path| {
+        let file_name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+        file_name == "_index.md" || i18n::file_language(&site.config, file_name).is_some_and(|(stem, ..)| stem == "_index")
+    });
+
+    let pages = par_try_map(&md_files, errors, |md_file| {
+        load_page(site, &md_file.display().to_string())
+    })?;
+    let (mut pages, resources) = published(site, pages, resources);
+    add_translations(&site.config, &mut pages);
+    let sections = par_try_map(&section_files, errors, |index_file| load_section(site, index_file))?;
+    // Unlisted pages are built, but no section or taxonomy lists them.
+    let listed: Vec<Page> = pages.iter().filter(|page| !page.meta.unlisted).cloned().collect();
+    let sections = section::assemble(&site.config, sections, &listed);
+    // The default language always has its taxonomies, the others only once they have pages.
+    let taxonomies = site
+        .config
+        .languages()
+        .into_iter()
+        .filter(|lang| *lang == site.config.language || listed.iter().any(|page| page.lang == *lang))
+        .flat_map(|lang| taxonomy::collect(&site.config, lang, &listed))
+        .collect();
+    let links = link_targets(site, &pages, &sections);
+    let content = Content { pages, sections, taxonomies, links, resources };
+
+    // Work out where every page is going before writing any of them, so that two
+    // sources ending up at the same output file fail the build instead of one
+    // silently overwriting the other.
+    let mut outputs: Vec<(String, PathBuf)> = content
+        .pages
+        .iter()
+        .map(|page| (page.source.display().to_string(), page.output_file.clone()))
+        .collect();
+    for listing in listings(site, &content) {
+        let source = listing.source(site).display().to_string();
+        for path in listing.paths(site) {
+            outputs.push((source.clone(), output_html_path(&path, &site.paths.output_path)));
+        }
+    }
+    let collisions = check_output_collisions(&outputs);
+    if !collisions.is_empty() {
+        errors.extend(collisions)?;
+        return Err(errors.abort());
+    }
+
+    Ok(content)
+}
+
+// Leaves out the drafts and the pages outside their publishing dates, unless the
+// site is built with `--drafts` or `--future`, along with the files in their
+// bundles. Logs how many pages each rule left out.
+fn published(site: &Site, pages: Vec<Page>, resources: Vec<PathBuf>) -> (Vec<Page>, Vec<PathBuf>) {
+    let now = Utc::now().fixed_offset();
+    let mut excluded: HashMap<Exclusion, usize> = HashMap::new();
+    let mut excluded_bundles = Vec::new();
+    let pages: Vec<Page> = pages
+        .into_iter()
+        .filter(|page| {
+            let Some(exclusion) = page.excluded(now, site.drafts, site.future) else {
+                return true;
+            };
+            *excluded.entry(exclusion).or_default() += 1;
+            // The content directory's own `index.md` isn't a bundle.
+            if page.is_index() && !page.section.is_empty() {
+                excluded_bundles.extend(page.source.parent().map(Path::to_path_buf));
+            }
+            false
         })
-    {
-        convert_file_to_html(site_paths, &entry.path().display().to_string());
+        .collect();
+    let resources = resources
+        .into_iter()
+        .filter(|resource| !excluded_bundles.iter().any(|bundle| resource.starts_with(bundle)))
+        .collect();
+
+    let count = |exclusion| excluded.get(&exclusion).copied().unwrap_or_default();
+    let unlisted = pages.iter().filter(|page| page.meta.unlisted).count();
+    if !excluded.is_empty() || unlisted > 0 {
+        info!(
+            "Building {} pages, left out {} drafts, {} scheduled and {} expired pages, {} unlisted pages aren't listed",
+            pages.len(),
+            count(Exclusion::Draft),
+            count(Exclusion::Scheduled),
+            count(Exclusion::Expired),
+            unlisted
+        );
     }
+    (pages, resources)
 }
 
-fn convert_file_to_html(site_paths: &SitePaths, md_file_path: &str) {
-    let markdown_input = fs::read_to_string(md_file_path);
-    match markdown_input {
-        Ok(markdown_text) => convert_md_text_to_html(site_paths, &md_file_path, &markdown_text),
-        Err(e) => println!("Operation failed: {}", e), // std::io::Error implements Display
+// Links every page to its translations: the pages made from the same file in
+// other languages, such as `intro.md`, `intro.de.md` and `de/intro.md`.
+fn add_translations(config: &Config, pages: &mut [Page]) {
+    let mut translations: HashMap<String, Vec<i18n::Translation>> = HashMap::new();
+    for page in pages.iter() {
+        translations.entry(page.untranslated_path.clone()).or_default().push(page.translation());
+    }
+    let languages = config.languages();
+    for page in pages.iter_mut() {
+        let mut variants = translations[&page.untranslated_path].clone();
+        variants.sort_by_key(|translation| languages.iter().position(|lang| *lang == translation.lang));
+        page.translations = variants;
+    }
+}
+
+// Every page and every section with an `_index.md`, keyed by source file, along
+// with the ids of their headings so links to those can be checked.
+fn link_targets(site: &Site, pages: &[Page], sections: &[Section]) -> LinkTargets {
+    let options = site.config.markdown.options();
+    let mut targets: LinkTargets = pages
+        .par_iter()
+        .map(|page| {
+            let anchors = headings::heading_ids(&page.markdown, options);
+            (page.relative_path.clone(), LinkTarget { permalink: page.permalink.clone(), anchors })
+        })
+        .collect();
+    for (section, _) in section::flatten(sections) {
+        if section.source.is_some() {
+            let relative_path = if section.directory.is_empty() {
+                String::from("_index.md")
+            } else {
+                format!("{}/_index.md", section.directory)
+            };
+            let anchors = headings::heading_ids(&section.markdown, options);
+            targets.insert(relative_path, LinkTarget { permalink: section.permalink.clone(), anchors });
+        }
     }
+    targets
 }
 
-fn convert_md_text_to_html(site_paths: &SitePaths, md_file_path: &str, markdown_text: &str) {
-    info!("Processing: {}", md_file_path);
+// A page made up by the generator rather than converted from a Markdown file.
+enum Listing<'a> {
+    Section { section: &'a Section, parent: Option<&'a Section> },
+    Taxonomy(&'a Taxonomy),
+    Term { taxonomy: &'a Taxonomy, term: &'a Term },
+}
 
-    // Set up options (e.g., enable tables, footnotes, etc.)
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_TABLES);
-    options.insert(Options::ENABLE_FOOTNOTES);
-    // Add more options as needed
+impl<'a> Listing<'a> {
+    fn path(&self) -> &str {
+        match self {
+            Listing::Section { section, .. } => &section.path,
+            Listing::Taxonomy(taxonomy) => &taxonomy.path,
+            Listing::Term { term, .. } => &term.link.path,
+        }
+    }
 
-    let parser = Parser::new_ext(&markdown_text, options);
+    // The file errors are reported against: a section's `_index.md` or directory,
+    // or the file a taxonomy page would be written to.
+    fn source(&self, site: &Site) -> PathBuf {
+        match self {
+            Listing::Section { section, .. } => match &section.source {
+                Some(source) => source.clone(),
+                None => Path::new(&site.paths.content_path).join(&section.directory),
+            },
+            _ => output_html_path(self.path(), &site.paths.output_path),
+        }
+    }
 
-    // Create a buffer to store the HTML output
-    let mut html_output = String::new();
-    html::push_html(&mut html_output, parser);
+    // Splits the listing into pages when its section or taxonomy sets
+    // `paginate_by`. Listings that aren't paginated get no pagers.
+    fn pagers(&self, site: &Site) -> Vec<Paginator<'a>> {
+        let (pages, paginate_by) = match *self {
+            Listing::Section { section, .. } => (&section.pages, section.meta.paginate_by),
+            Listing::Taxonomy(_) => return Vec::new(),
+            Listing::Term { taxonomy, term } => {
+                (&term.pages, site.config.taxonomy(&taxonomy.name).and_then(|taxonomy| taxonomy.paginate_by))
+            },
+        };
+        match paginate_by {
+            Some(paginate_by) => pagination::paginate(&site.config, &self.directory(site), self.path(), pages, paginate_by),
+            None => Vec::new(),
+        }
+    }
+
+    // Every URL the listing writes a page to: one per pager when it is paginated,
+    // plus the `page/1/` that redirects to the first.
+    fn paths(&self, site: &Site) -> Vec<String> {
+        let pagers = self.pagers(site);
+        if pagers.is_empty() {
+            return vec![self.path().to_string()];
+        }
+        let first_pager = pagination::pager_path(&site.config, &self.directory(site), 1);
+        pagers.into_iter().map(|pager| pager.path).chain([first_pager]).collect()
+    }
 
-    let rendered_html = match render_page(&site_paths, &html_output) {
-        Ok(html) => html,
-        Err(e) => {
-            panic!("Error rendering template: {}", e);
+    // The date of the newest page the listing lists.
+    fn updated(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Listing::Section { section, .. } => section.pages.iter().filter_map(|page| page.datetime).max(),
+            Listing::Taxonomy(taxonomy) => {
+                taxonomy.terms.iter().flat_map(|term| &term.pages).filter_map(|page| page.datetime).max()
+            },
+            Listing::Term { term, .. } => term.pages.iter().filter_map(|page| page.datetime).max(),
+        }
+    }
+
+    // Where the listing's pages go, e.g. `de/blog` for the German blog.
+    fn directory(&self, site: &Site) -> String {
+        let directory = match self {
+            Listing::Section { section, .. } => section.directory.clone(),
+            Listing::Taxonomy(taxonomy) => taxonomy.name.clone(),
+            Listing::Term { taxonomy, term } => format!("{}/{}", taxonomy.name, term.link.slug),
+        };
+        site.config.language_directory(self.lang(), &directory)
+    }
+
+    fn lang(&self) -> &str {
+        match self {
+            Listing::Section { section, .. } => &section.lang,
+            Listing::Taxonomy(taxonomy) | Listing::Term { taxonomy, .. } => &taxonomy.lang,
+        }
+    }
+}
+
+// Every section with an `_index.md` gets an index page, and the others do if there
+// is a template for them. Every taxonomy gets a page listing its terms and a page
+// for each term.
+fn listings<'a>(site: &Site, content: &'a Content) -> Vec<Listing<'a>> {
+    let mut listings: Vec<Listing> = section::flatten(&content.sections)
+        .into_iter()
+        .filter(|(section, _)| {
+            section.source.is_some()
+                || find_section_template(&site.tera, &section.directory, section.meta.template.as_deref()).is_ok()
+        })
+        .map(|(section, parent)| Listing::Section { section, parent })
+        .collect();
+
+    for taxonomy in &content.taxonomies {
+        listings.push(Listing::Taxonomy(taxonomy));
+        listings.extend(taxonomy.terms.iter().map(|term| Listing::Term { taxonomy, term }));
+    }
+    listings
+}
+
+// The whole site gets a feed, and so does every section that sets
+// `generate_feeds` and every term of a taxonomy that sets `feeds`.
+fn feeds<'a>(site: &Site, content: &'a Content) -> Vec<Feed<'a>> {
+    let config = &site.config;
+    if !config.feeds.enabled() {
+        return Vec::new();
+    }
+
+    // Every language with pages gets a feed of its own, under `/de/` and so on.
+    let mut feeds = Vec::new();
+    for lang in config.languages() {
+        let pages = content.pages.iter().filter(|page| page.lang == lang);
+        if lang != config.language && pages.clone().next().is_none() {
+            continue;
+        }
+        // The site feed links to the site itself, whether or not there is a
+        // listing at its root.
+        let directory = config.language_directory(lang, "");
+        let home = match directory.as_str() {
+            "" => format!("{}/", config.base_url.trim_end_matches('/')),
+            directory => format!("{}/{}/", config.base_url.trim_end_matches('/'), directory),
+        };
+        feeds.push(Feed::new(config, lang, config.title_in(lang), None, &home, &directory, pages));
+    }
+    for (section, _) in section::flatten(&content.sections) {
+        if section.meta.generate_feeds {
+            let title = section.meta.title.as_deref().unwrap_or_default();
+            let description = section.meta.description.as_deref();
+            let directory = config.language_directory(&section.lang, &section.directory);
+            feeds.push(Feed::new(config, &section.lang, title, description, &section.permalink, &directory, &section.pages));
+        }
+    }
+    for taxonomy in &content.taxonomies {
+        if config.taxonomy(&taxonomy.name).is_some_and(|declared| declared.feeds) {
+            for term in &taxonomy.terms {
+                let title = format!("{} - {}", config.title_in(&taxonomy.lang), term.link.name);
+                let directory = config.language_directory(&taxonomy.lang, &format!("{}/{}", taxonomy.name, term.link.slug));
+                feeds.push(Feed::new(config, &taxonomy.lang, &title, None, &term.link.permalink, &directory, &term.pages));
+            }
+        }
+    }
+    feeds
+}
+
+// Every page the sitemap lists: the pages other than drafts and unlisted ones,
+// and every page of every listing.
+fn sitemap_entries(site: &Site, content: &Content) -> Vec<SitemapEntry> {
+    let base_url = site.config.base_url.trim_end_matches('/');
+    let mut entries: Vec<SitemapEntry> = content
+        .pages
+        .iter()
+        .filter(|page| !page.meta.draft && !page.meta.unlisted)
+        .map(|page| SitemapEntry {
+            permalink: page.permalink.clone(),
+            lastmod: page.datetime,
+            changefreq: page.meta.changefreq,
+            priority: page.meta.priority,
+            alternates: if page.translations.len() > 1 {
+                page.translations.iter().map(|translation| (translation.lang.clone(), translation.permalink.clone())).collect()
+            } else {
+                Vec::new()
+            },
+        })
+        .collect();
+
+    for listing in listings(site, content) {
+        let lastmod = listing.updated();
+        let pagers = listing.pagers(site);
+        let paths = if pagers.is_empty() {
+            vec![listing.path().to_string()]
+        } else {
+            pagers.into_iter().map(|pager| pager.path).collect()
+        };
+        entries.extend(paths.into_iter().map(|path| SitemapEntry {
+            permalink: format!("{}{}", base_url, path),
+            lastmod,
+            changefreq: None,
+            priority: None,
+            alternates: Vec::new(),
+        }));
+    }
+    entries
+}
+
+// Runs `f` over `items` on the worker threads. The results come back in the same
+// order as `items`, so the output doesn't depend on which thread finished first;
+// items that failed are left out and their errors recorded in `errors`.
+fn par_try_map<T, U>(
+    items: &[T],
+    errors: &mut ErrorCollector,
+    f: impl Fn(&T) -> Result<U, BuildError> + Sync + Send,
+) -> Result<Vec<U>, BuildErrors>
+where
+    T: Sync,
+    U: Send,
+{
+    if errors.fail_fast() {
+        // Collecting into a `Result` stops handing out work after the first error.
+        return match items.par_iter().map(f).collect::<Result<Vec<U>, BuildError>>() {
+            Ok(results) => Ok(results),
+            Err(error) => errors.extend([error]).map(|()| Vec::new()),
+        };
+    }
+
+    let mut results = Vec::with_capacity(items.len());
+    for result in items.par_iter().map(f).collect::<Vec<_>>() {
+        results.extend(errors.check(result)?);
+    }
+    Ok(results)
+}
+
+// Builds the site and then keeps rebuilding it as files change, passing the
+// outcome of every build to `after_build`. A failed build is reported but doesn't
+// stop the watcher, so the next save gets another go. That goes for a site that
+// can't even be loaded, say over a broken template, too.
+fn watch_site(cli: &Cli, after_build: impl Fn(&Result<()>)) -> Result<()> {
+    let mut site = None;
+    let result = load_and_build(cli, &mut site);
+    if let Err(e) = &result {
+        error!("Build failed: {:#}", e);
+    }
+    after_build(&result);
+
+    let config = watched_config(cli);
+    let watched = [
+        PathBuf::from(&config.content_dir),
+        PathBuf::from(&config.template_dir),
+        PathBuf::from(&config.static_dir),
+        PathBuf::from(&config.i18n_dir),
+        PathBuf::from(&config.sass_dir),
+        PathBuf::from(config_path(cli)),
+    ];
+    watch::watch(&watched, |changed| {
+        let result = match &mut site {
+            Some(site) => rebuild(cli, site, changed),
+            None => load_and_build(cli, &mut site),
+        };
+        if let Err(e) = &result {
+            error!("Build failed: {:#}", e);
+        }
+        after_build(&result);
+    })
+}
+
+// Loads the site into `site` and builds all of it.
+fn load_and_build(cli: &Cli, site: &mut Option<Site>) -> Result<()> {
+    let site = site.insert(load_site(cli)?);
+    Ok(convert_files(site)?)
+}
+
+// Rebuilds as little of the site as the changed files allow: edited Markdown
+// files are converted on their own, static files are copied on their own, the
+// stylesheets are recompiled on their own, and anything else (templates, new or
+// deleted pages, the config) rebuilds everything.
+fn rebuild(cli: &Cli, site: &mut Site, changed: &[PathBuf]) -> Result<()> {
+    let config_file = Path::new(config_path(cli));
+    let i18n_dir = Path::new(&site.config.i18n_dir);
+    if changed.iter().any(|path| relative_to(path, config_file).is_some() || relative_to(path, i18n_dir).is_some()) {
+        info!("Config or translations changed, rebuilding everything");
+        *site = load_site(cli)?;
+        return Ok(convert_files(site)?);
+    }
+
+    let content_dir = Path::new(&site.paths.content_path);
+    let static_dir = Path::new(&site.paths.static_path);
+    let template_dir = Path::new(&site.config.template_dir);
+    let sass_dir = Path::new(&site.config.sass_dir);
+    let changed: Vec<&PathBuf> = changed
+        .iter()
+        .filter(|path| {
+            [content_dir, static_dir, template_dir, sass_dir].iter().any(|dir| relative_to(path, dir).is_some())
+        })
+        .collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    // Any stylesheet might import a partial that changed, so they're all compiled again.
+    if changed.iter().all(|path| relative_to(path, sass_dir).is_some()) {
+        let mut errors = ErrorCollector::new(site.fail_fast);
+        compile_sass(site, &mut errors)?;
+        return Ok(errors.finish()?);
+    }
+
+    // Markdown files that were edited in place only need their own page rebuilt,
+    // along with the section and taxonomy pages that might list them.
+    let edited_pages: Option<Vec<PathBuf>> = changed
+        .iter()
+        .map(|path| {
+            let is_markdown = path.extension().and_then(OsStr::to_str) == Some("md");
+            let is_section = path.file_name() == Some(OsStr::new("_index.md"));
+            let relative = relative_to(path, content_dir).filter(|_| is_markdown && !is_section && path.is_file())?;
+            Some(content_dir.join(relative))
+        })
+        .collect();
+    if let Some(md_files) = edited_pages {
+        let mut errors = ErrorCollector::new(site.fail_fast);
+        let content = load_content(site, &mut errors)?;
+        let pages: Vec<&Page> = content.pages.iter().filter(|page| md_files.contains(&page.source)).collect();
+        par_try_map(&pages, &mut errors, |page| {
+            info!("Processing: {}", page.source.display());
+            convert_file_to_html(site, &content, page)
+        })?;
+        let listings = listings(site, &content);
+        par_try_map(&listings, &mut errors, |listing| convert_listing_to_html(site, &content, listing))?;
+        par_try_map(&feeds(site, &content), &mut errors, |feed| write_feed(site, &content, feed))?;
+        errors.check(write_sitemap(site, &content))?;
+        return Ok(errors.finish()?);
+    }
+
+    if changed.iter().all(|path| relative_to(path, static_dir).is_some()) {
+        assets::copy_dir(static_dir, Path::new(&site.paths.output_path))?;
+        return Ok(());
+    }
+
+    if changed.iter().any(|path| relative_to(path, template_dir).is_some()) {
+        info!("Templates changed, reloading them");
+        site.tera = load_templates(&site.paths.template_path)?;
+        register_functions(&mut site.tera, &site.config, &site.images)?;
+    }
+
+    info!("Rebuilding everything");
+    Ok(convert_files(site)?)
+}
+
+// Works out where `path` is relative to `base`, where either may be relative to
+// the current directory or absolute (the file watcher reports absolute paths).
+fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path = std::path::absolute(path).ok()?;
+    let base = base.canonicalize().or_else(|_| std::path::absolute(base)).ok()?;
+    let path = path
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .and_then(|parent| Some(parent.join(path.file_name()?)))
+        .unwrap_or(path);
+    if path == base {
+        return Some(PathBuf::new());
+    }
+    path.strip_prefix(&base).ok().map(Path::to_path_buf)
+}
+
+fn check_output_collisions(outputs: &[(String, PathBuf)]) -> Vec<BuildError> {
+    let mut written_by: HashMap<&Path, &str> = HashMap::new();
+    let mut errors = Vec::new();
+    for (source, output_file) in outputs {
+        if let Some(other) = written_by.insert(output_file, source) {
+            errors.push(BuildError::new(
+                Stage::Write,
+                source,
+                anyhow!("{} and {} would both be written to {}", other, source, output_file.display()),
+            ));
+        }
+    }
+    errors
+}
+
+// Reads a Markdown file and its front matter and works out where the page goes.
+fn load_page(site: &Site, md_file_path: &str) -> Result<Page, BuildError> {
+    let markdown_text = fs::read_to_string(md_file_path)
+        .map_err(|e| BuildError::new(Stage::Read, md_file_path, e))?;
+
+    // Split the front matter off so only the Markdown body goes to the parser.
+    let (meta, markdown_body) = split_front_matter(&markdown_text)
+        .map_err(|e| BuildError::new(Stage::Parse, md_file_path, e))?;
+
+    let mut page = Page::new(&site.config, Path::new(md_file_path), &site.paths.content_path, meta, markdown_body)
+        .map_err(|e| BuildError::new(Stage::Parse, md_file_path, e))?;
+    page.output_file = output_html_path(&page.path, &site.paths.output_path);
+    page.markdown_line = body_line(&markdown_text, markdown_body);
+
+    Ok(page)
+}
+
+// Reads the `_index.md` of a section.
+fn load_section(site: &Site, index_file: &Path) -> Result<Section, BuildError> {
+    let markdown_text = fs::read_to_string(index_file)
+        .map_err(|e| BuildError::new(Stage::Read, index_file, e))?;
+    let (meta, markdown_body) = parse_front_matter::<SectionMeta>(&markdown_text)
+        .map_err(|e| BuildError::new(Stage::Parse, index_file, e))?;
+
+    // `_index.de.md` and `de/_index.md` are both the German `_index.md`.
+    let relative = index_file.strip_prefix(&site.paths.content_path).unwrap_or(index_file);
+    let (lang, untranslated) = i18n::split_language(&site.config, relative);
+    let directory = untranslated.parent().map(url_components).unwrap_or_default();
+
+    let mut section = Section::new(&site.config, &lang, &directory, Some(index_file.to_path_buf()), meta, markdown_body);
+    section.markdown_line = body_line(&markdown_text, markdown_body);
+    Ok(section)
+}
+
+// The line of `text` that `body`, the part of it below the front matter, starts on.
+fn body_line(text: &str, body: &str) -> usize {
+    text[..text.len() - body.len()].matches('\n').count() + 1
+}
+
+fn convert_file_to_html(site: &Site, content: &Content, page: &Page) -> Result<(), BuildError> {
+    let (html_output, toc) = convert_md_text_to_html(site, content, &page.source.display().to_string(), &page.markdown, page.markdown_line)
+        .map_err(|e| BuildError::new(Stage::Parse, &page.source, e))?;
+    // `toc = false` in the front matter leaves the table of contents empty.
+    let toc = if page.meta.toc == Some(false) { Vec::new() } else { toc };
+
+    let rendered_html = render_page(site, content, page, &html_output, &toc)
+        .map_err(|e| BuildError::new(Stage::Render, &page.source, e))?;
+
+    write_html(site, &page.source, &page.output_file, rendered_html)
+}
+
+fn convert_listing_to_html(site: &Site, content: &Content, listing: &Listing) -> Result<(), BuildError> {
+    let source = listing.source(site);
+    let html_output = match listing {
+        Listing::Section { section, .. } => {
+            convert_md_text_to_html(site, content, &source.display().to_string(), &section.markdown, section.markdown_line)
+                .map_err(|e| BuildError::new(Stage::Parse, &source, e))?
+                .0
         },
+        _ => String::new(),
     };
 
-    let output_file = output_html_path(md_file_path, &site_paths.output_path);
+    let pagers = listing.pagers(site);
+    if pagers.is_empty() {
+        let rendered_html = render_listing(site, content, listing, None, &html_output)
+            .map_err(|e| BuildError::new(Stage::Render, &source, e))?;
+        return write_html(site, &source, &output_html_path(listing.path(), &site.paths.output_path), rendered_html);
+    }
+
+    for pager in &pagers {
+        let rendered_html = render_listing(site, content, listing, Some(pager), &html_output)
+            .map_err(|e| BuildError::new(Stage::Render, &source, e))?;
+        write_html(site, &source, &output_html_path(&pager.path, &site.paths.output_path), rendered_html)?;
+    }
+
+    // `page/1/` is the listing's own page.
+    let first_pager = pagination::pager_path(&site.config, &listing.directory(site), 1);
+    write_html(
+        site,
+        &source,
+        &output_html_path(&first_pager, &site.paths.output_path),
+        pagination::redirect_html(&pagers[0].first),
+    )
+}
+
+// Writes the feed's `rss.xml` and `atom.xml`, whichever of them are turned on.
+fn write_feed(site: &Site, content: &Content, feed: &Feed) -> Result<(), BuildError> {
+    let entries: Vec<FeedEntry> = feed
+        .pages
+        .iter()
+        .map(|page| {
+            let source = page.source.display().to_string();
+            let convert = |markdown: &str| {
+                convert_md_text_to_html(site, content, &source, markdown, page.markdown_line).map(|(html, _)| html)
+            };
+            // Pages are summarised by the part above `<!-- more -->` or their
+            // description, and go in whole when they have neither.
+            let summary = match (page.summary(), page.meta.description.as_deref()) {
+                _ if site.config.feeds.full_content => None,
+                (Some(summary), _) => Some(convert(summary)),
+                (None, Some(description)) => Some(Ok(format!("<p>{}</p>", tera::escape_html(description)))),
+                (None, None) => None,
+            };
+            let is_summary = summary.is_some();
+            let html = summary
+                .unwrap_or_else(|| convert(&page.markdown))
+                .map_err(|e| BuildError::new(Stage::Parse, &page.source, e))?;
+            Ok(FeedEntry { page, html, is_summary })
+        })
+        .collect::<Result<_, BuildError>>()?;
+
+    let mut files = Vec::new();
+    if site.config.feeds.rss {
+        files.push(("rss.xml", feed::rss(&site.config, feed, &entries)));
+    }
+    if site.config.feeds.atom {
+        files.push(("atom.xml", feed::atom(&site.config, feed, &entries)));
+    }
+    for (file_name, xml) in files {
+        let output_file = output_html_path(&feed.path(file_name), &site.paths.output_path);
+        info!("Writing feed: {}", output_file.display());
+        create_and_write_file(&output_file, &xml)
+            .with_context(|| format!("failed to write {}", output_file.display()))
+            .map_err(|e| BuildError::new(Stage::Write, &output_file, e))?;
+    }
+    Ok(())
+}
+
+// Writes `sitemap.xml` (and the sitemaps it indexes, for big sites) and a
+// `robots.txt` pointing at it. A `robots.txt` in the templates replaces the
+// default one, and gets the sitemap's URL as `sitemap`.
+fn write_sitemap(site: &Site, content: &Content) -> Result<(), BuildError> {
+    let entries = sitemap_entries(site, content);
+    let mut files = sitemap::sitemaps(&site.config.base_url, &entries, sitemap::MAX_URLS);
+
+    let sitemap_url = format!("{}/sitemap.xml", site.config.base_url.trim_end_matches('/'));
+    let robots_txt = if site.tera.get_template_names().any(|name| name == "robots.txt") {
+        let mut context = base_context(site, content, &site.config.language);
+        context.insert("sitemap", &sitemap_url);
+        site.tera
+            .render("robots.txt", &context)
+            .map_err(anyhow::Error::from)
+            .context("failed to render robots.txt")
+            .map_err(|e| BuildError::new(Stage::Render, Path::new(&site.config.template_dir).join("robots.txt"), e))?
+    } else {
+        sitemap::default_robots_txt(&sitemap_url)
+    };
+    files.push((String::from("/robots.txt"), robots_txt));
+
+    for (path, text) in files {
+        let output_file = output_html_path(&path, &site.paths.output_path);
+        create_and_write_file(&output_file, &text)
+            .with_context(|| format!("failed to write {}", output_file.display()))
+            .map_err(|e| BuildError::new(Stage::Write, &output_file, e))?;
+    }
+    Ok(())
+}
+
+fn write_html(site: &Site, source: &Path, output_file: &Path, mut rendered_html: String) -> Result<(), BuildError> {
+    if site.live_reload {
+        rendered_html = serve::inject_live_reload(&rendered_html);
+    }
 
     // Create the output directory if it doesn't exist and write the file.
     info!("Writing output: {}", output_file.display());
-    if let Err(e) = create_and_write_file(&output_file, &rendered_html) {
-        eprintln!("Operation failed: {}", e);
-    }
+    create_and_write_file(output_file, &rendered_html)
+        .with_context(|| format!("failed to write {}", output_file.display()))
+        .map_err(|e| BuildError::new(Stage::Write, source, e))?;
+
+    Ok(())
+}
+
+// Returns the page's HTML along with its table of contents. `first_line` is the
+// line of the file the Markdown starts on, for errors.
+fn convert_md_text_to_html(
+    site: &Site,
+    content: &Content,
+    md_file_path: &str,
+    markdown_text: &str,
+    first_line: usize,
+) -> Result<(String, Vec<TocEntry>)> {
+
+    // The Markdown extensions (tables, footnotes, etc.) come from the site config.
+    let options = site.config.markdown.options();
+
+    // Shortcodes are rendered first, and wait as placeholders while the Markdown
+    // around them is converted. The bodies of block shortcodes are Markdown too.
+    let (markdown_text, shortcodes) =
+        shortcodes::render_shortcodes(&site.tera, markdown_text, first_line, options, &|body, line| {
+            convert_md_text_to_html(site, content, md_file_path, body, line).map(|(html, _)| html)
+        })?;
+
+    // Links to Markdown files become links to the pages made from them.
+    let current = relative_to(Path::new(md_file_path), Path::new(&site.paths.content_path))
+        .map(|relative| url_components(&relative))
+        .unwrap_or_default();
+    let events = Parser::new_ext(&markdown_text, options)
+        .into_offset_iter()
+        .map(|(event, range)| {
+            let event = links::resolve_link(event, &current, &content.links).with_context(|| {
+                format!("on line {}", first_line + markdown_text[..range.start].matches('\n').count())
+            })?;
+            Ok((event, range))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Images next to the page or in the static directory get resized versions.
+    let events = if site.config.images.markdown {
+        images::replace_images(events, |url, range| {
+            if !images::is_local_image(url) {
+                return Ok(None);
+            }
+            let source = match url.strip_prefix('/') {
+                Some(path) => Path::new(&site.paths.static_path).join(path),
+                None => Path::new(md_file_path).parent().unwrap_or(Path::new("")).join(url),
+            };
+            site.images.process(&source).map(Some).with_context(|| {
+                format!("on line {}", first_line + markdown_text[..range.start].matches('\n').count())
+            })
+        })?
+    } else {
+        events
+    };
+
+    let events = if site.config.highlighting.enabled {
+        highlight::highlight_code_blocks(&markdown_text, first_line, events.into_iter(), &site.config.highlighting)?
+    } else {
+        events.into_iter().map(|(event, _)| event).collect()
+    };
+    let (events, toc) = headings::anchor_headings(events, site.config.markdown.heading_anchors);
+
+    // Create a buffer to store the HTML output
+    let mut html_output = String::new();
+    html::push_html(&mut html_output, events.into_iter());
+
+    Ok((shortcodes::insert_shortcodes(&html_output, &shortcodes), toc))
+}
+
+// The variables every template gets, for a page in the language `lang`.
+fn base_context(site: &Site, content: &Content, lang: &str) -> Context {
+    let mut context = Context::new();
+    context.insert("config", &site.config);
+    context.insert("lang", lang);
+    context.insert("taxonomies", &taxonomy::summaries(&content.taxonomies, lang));
+    context
+}
+
+// What templates see as `page`: the page along with its table of contents.
+#[derive(serde::Serialize)]
+struct PageContext<'a> {
+    #[serde(flatten)]
+    page: &'a Page,
+    toc: &'a [TocEntry],
 }
 
 // The function must return a Result to use the '?' operator.
-fn render_page(site_paths: &SitePaths, html_output: &str) -> Result<String, tera::Error> {
-    let tera = Tera::new(&site_paths.template_path)?;
+fn render_page(site: &Site, content: &Content, page: &Page, html_output: &str, toc: &[TocEntry]) -> Result<String> {
+    // The page picks its template, otherwise its section does, otherwise `base.html`.
+    let template = find_template(&site.tera, &page.section, page.meta.template.as_deref(), &site.paths.base_template)?;
 
     // Create a context and add the data into it.
-    let mut context = Context::new();
-    context.insert("title", "The Title");
+    let mut context = base_context(site, content, &page.lang);
+    context.insert("title", page.meta.title.as_deref().unwrap_or_default());
+    context.insert("page", &PageContext { page, toc });
     context.insert("content", &html_output);
 
     // Render the html from the template and the context.
-    let rendered_html = tera.render(&site_paths.base_template, &context)?;
+    let rendered_html = site.tera.render(&template, &context)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("failed to render {}", template))?;
+
+    Ok(rendered_html)
+}
+
+// Renders a section's index page, which lists its pages and subsections, or one
+// of the pages of a taxonomy. Paginated listings are rendered once per pager.
+fn render_listing(
+    site: &Site,
+    content: &Content,
+    listing: &Listing,
+    paginator: Option<&Paginator>,
+    html_output: &str,
+) -> Result<String> {
+    let mut context = base_context(site, content, listing.lang());
+    context.insert("content", &html_output);
+    if let Some(paginator) = paginator {
+        context.insert("paginator", paginator);
+    }
+    let template = match listing {
+        Listing::Section { section, parent } => {
+            context.insert("title", section.meta.title.as_deref().unwrap_or_default());
+            context.insert("section", section);
+            context.insert("parent_section", parent);
+            find_section_template(&site.tera, &section.directory, section.meta.template.as_deref())?
+        },
+        Listing::Taxonomy(taxonomy) => {
+            context.insert("title", &taxonomy.name);
+            context.insert("taxonomy", taxonomy);
+            find_taxonomy_template(&site.tera, &taxonomy.name, "list")?
+        },
+        Listing::Term { taxonomy, term } => {
+            context.insert("title", &term.link.name);
+            context.insert("taxonomy", &taxonomy.summary());
+            context.insert("term", term);
+            find_taxonomy_template(&site.tera, &taxonomy.name, "single")?
+        },
+    };
+
+    let rendered_html = site.tera.render(&template, &context)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("failed to render {}", template))?;
 
     Ok(rendered_html)
 }
@@ -123,42 +1192,29 @@ fn create_and_write_file(path: &Path, content: &str) -> io::Result<()> {
         fs::create_dir_all(parent)?; // The '?' operator propagates errors
     }
 
-    match fs::File::create(path) {
-        Ok(mut file) => {
-            match file.write_all(content.as_bytes()) {
-                Ok(_) => {
-                    info!("Successfully wrote to file: {:?}", path);
-                    Ok(())
-                }
-                Err(e) => {
-                    eprintln!("Error writing to file {:?}: {}", path, e);
-                    Err(e)
-                }
-            }
-        }
-        Err(e) => {
-            eprintln!("Error creating file {:?}: {}", path, e);
-            Err(e)
-        }
-    }
-}
+    let mut file = fs::File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    info!("Successfully wrote to file: {:?}", path);
 
-fn output_html_path(md_path: &str, output_dir: &str) -> PathBuf {
-    let md_path = Path::new(md_path);
-    let output_dir = Path::new(output_dir);
+    Ok(())
+}
 
-    // Get just the filename ("hello.md"). Since we got the md_path from reading it,
-    // this really shouldn't ever happen. If it does, just exit with the error message.
-    let filename = md_path.file_stem().unwrap_or_else( || {
-        panic!("Path has no file stem: {:?}", md_path);
-    });
+// Maps a page URL onto a file in the output directory. URLs ending in a `/` are
+// directories, so the page becomes the `index.html` inside them.
+fn output_html_path(url_path: &str, output_dir: &str) -> PathBuf {
+    let mut output_file = Path::new(output_dir).join(url_path.trim_start_matches('/'));
+    if url_path.ends_with('/') {
+        output_file.push("index.html");
+    }
 
-    // Build new path: output_dir + "hello.html"
-    output_dir.join(format!("{}.html", filename.to_string_lossy()))
+    // Build new path: output_dir + "blog/hello.html" or "blog/hello/index.html"
+    output_file
 }
 
 
 #[cfg(test)]
+// The argument tests borrow their arrays, which clap takes either way.
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests {
     use super::*;
     use std::fs;
@@ -166,6 +1222,12 @@ mod tests {
     use super::Cli;
     use clap::Parser;
     use tempfile::tempdir; // add `tempfile = "3"` to Cargo.toml dev-dependencies
+    use config::{TaxonomyConfig, UrlStyle};
+    use front_matter::PageMeta;
+
+    fn test_page(meta: PageMeta) -> Page {
+        Page::new(&Config::default(), Path::new("./content/test.md"), "./content", meta, "").unwrap()
+    }
 
     #[test]
     fn test_convert_md_text_to_html_basic() {
@@ -176,11 +1238,21 @@ mod tests {
         // Minimal template string to simulate Tera
         //let template_dir = "tests/templates/*.html";
 
-        let site_paths = SitePaths {
-            content_path: String::from("./tests/content"),
-            template_path: String::from("./tests/templates/*.html"),
-            output_path: String::from("./tests/output"),
-            base_template: String::from("base.html"),
+        let site = Site {
+            paths: SitePaths {
+                content_path: String::from("./tests/content"),
+                template_path: String::from("./tests/templates/*.html"),
+                output_path: String::from("./tests/output"),
+                static_path: String::from("./tests/static"),
+                base_template: String::from("base.html"),
+            },
+            config: Config::default(),
+            live_reload: false,
+            fail_fast: false,
+            drafts: false,
+            future: false,
+            tera: Tera::default(),
+            images: Arc::default(),
         };
 
         // Ensure test template exists
@@ -188,15 +1260,16 @@ mod tests {
         fs::write("tests/templates/base.html", "<html><head><title>{{ title }}</title></head><body>{{ content | safe }}</body></html>").unwrap();
 
         // Act: convert
-        convert_md_text_to_html(&site_paths, &md_path, md);
+        let (html_output, _) = convert_md_text_to_html(&site, &Content::default(), md_path, md, 1).unwrap();
+        assert_eq!(html_output, "<h1 id=\"hello\">Hello</h1>\n<p>This is a test.</p>\n");
 
         // Assert: just check template exists, tera loads it, and HTML is generated
         // (Here we don’t capture stdout, but you could with `assert_cmd` or `duct`)
-        let tera = Tera::new(&site_paths.template_path).unwrap();
+        let tera = Tera::new(&site.paths.template_path).unwrap();
         let mut ctx = Context::new();
         ctx.insert("title", "The Title");
         ctx.insert("content", "<h1>Hello</h1>\n<p>This is a test.</p>\n");
-        let rendered = tera.render(&site_paths.base_template, &ctx).unwrap();
+        let rendered = tera.render(&site.paths.base_template, &ctx).unwrap();
 
         assert!(rendered.contains("<h1>Hello</h1>"));
         assert!(rendered.contains("<p>This is a test.</p>"));
@@ -204,19 +1277,29 @@ mod tests {
     }
 
     #[test]
-    fn test_convert_file_to_html_missing_file() {
+    fn test_load_page_missing_file() {
         // Arrange: point to a missing file
         let missing_path = "tests/fixtures/does_not_exist.md";
 
-        let site_paths = SitePaths {
-            content_path: String::from("./tests/content"),
-            template_path: String::from("./tests/templates/*.html"),
-            output_path: String::from("./tests/output"),
-            base_template: String::from("base.html"),
+        let site = Site {
+            paths: SitePaths {
+                content_path: String::from("./tests/content"),
+                template_path: String::from("./tests/templates/*.html"),
+                output_path: String::from("./tests/output"),
+                static_path: String::from("./tests/static"),
+                base_template: String::from("base.html"),
+            },
+            config: Config::default(),
+            live_reload: false,
+            fail_fast: false,
+            drafts: false,
+            future: false,
+            tera: Tera::default(),
+            images: Arc::default(),
         };
 
         // Act: function should not panic
-        convert_file_to_html(&site_paths, missing_path);
+        assert!(load_page(&site, missing_path).is_err());
 
         // Assert: nothing to assert directly, but no panic = pass
         assert!(!Path::new(missing_path).exists());
@@ -224,13 +1307,783 @@ mod tests {
 
     #[test]
     fn test_output_html_path() {
-        let md = "./content/hello.md";
+        let url = "/hello.html";
         let out = "./output";
-        let result = output_html_path(md, out);
+        let result = output_html_path(url, out);
 
         assert_eq!(result, PathBuf::from("./output/hello.html"));
     }
 
+    #[test]
+    fn test_output_html_path_keeps_directories() {
+        assert_eq!(
+            output_html_path("/blog/intro.html", "./output"),
+            PathBuf::from("./output/blog/intro.html")
+        );
+        assert_eq!(
+            output_html_path("/docs/intro/", "./output"),
+            PathBuf::from("./output/docs/intro/index.html")
+        );
+        assert_eq!(output_html_path("/", "./output"), PathBuf::from("./output/index.html"));
+    }
+
+    #[test]
+    fn test_check_output_collisions() {
+        let outputs = vec![
+            (String::from("content/a.md"), PathBuf::from("output/a.html")),
+            (String::from("content/b.md"), PathBuf::from("output/b.html")),
+        ];
+        assert!(check_output_collisions(&outputs).is_empty());
+
+        let outputs = vec![
+            (String::from("content/intro.md"), PathBuf::from("output/intro.html")),
+            (String::from("content/other/intro.md"), PathBuf::from("output/intro.html")),
+        ];
+        let errors = check_output_collisions(&outputs);
+        assert_eq!(errors.len(), 1);
+        let error = format!("{:#}", errors[0].cause());
+        assert!(error.contains("content/intro.md"), "{}", error);
+        assert!(error.contains("content/other/intro.md"), "{}", error);
+    }
+
+    #[test]
+    fn test_convert_files_mirrors_content_tree() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(content.join("blog")).unwrap();
+        fs::create_dir_all(content.join("docs")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(content.join("blog/intro.md"), "# Blog").unwrap();
+        fs::write(content.join("docs/intro.md"), "# Docs").unwrap();
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: dir.path().join("output").display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.path().join("output/blog/intro.html")).unwrap(), "Blog");
+        assert_eq!(fs::read_to_string(dir.path().join("output/docs/intro.html")).unwrap(), "Docs");
+    }
+
+    #[test]
+    fn test_convert_files_renders_sections() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("blog/2024")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(content.join("about.md"), "# About").unwrap();
+        fs::write(content.join("blog/_index.md"), "---\ntitle: Blog\nsort_by: date\n---\nAll the *posts*.").unwrap();
+        fs::write(content.join("blog/old.md"), "---\ndate: 2023-01-01\n---\n# Old").unwrap();
+        fs::write(content.join("blog/new.md"), "---\ndate: 2024-01-01\n---\n# New").unwrap();
+        fs::write(content.join("blog/2024/march.md"), "# March").unwrap();
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+        fs::write(
+            templates.join("section.html"),
+            "{{ title }}|{{ content | safe }}|{% for page in section.pages %}{{ page.title }} {% endfor %}|\
+             {% for sub in section.subsections %}{{ sub.title }} {% endfor %}|\
+             {% if parent_section %}{{ parent_section.path | safe }}{% endif %}",
+        )
+        .unwrap();
+
+        let site = Site::new(Config {
+            title: String::from("Home"),
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        assert_eq!(fs::read_to_string(output.join("index.html")).unwrap(), "Home||About |Blog |");
+        assert_eq!(
+            fs::read_to_string(output.join("blog/index.html")).unwrap(),
+            "Blog|<p>All the <em>posts</em>.</p>\n|New Old |2024 |/index.html"
+        );
+        assert_eq!(fs::read_to_string(output.join("blog/2024/index.html")).unwrap(), "2024||March ||/blog/index.html");
+        assert!(!output.join("blog/_index.html").exists());
+
+        // Editing a page updates the listings it is in.
+        let cli = Cli::parse_from(["test", "watch", "--config", dir.path().join("rusty_ssg.toml").to_str().unwrap()]);
+        let mut site = site;
+        fs::write(content.join("blog/old.md"), "---\ndate: 2023-01-01\n---\n# Older").unwrap();
+        rebuild(&cli, &mut site, &[content.join("blog/old.md")]).unwrap();
+        assert_eq!(fs::read_to_string(output.join("blog/old.html")).unwrap(), "Older");
+        assert!(fs::read_to_string(output.join("blog/index.html")).unwrap().contains("New Older"));
+    }
+
+    #[test]
+    fn test_convert_files_renders_taxonomies() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(&content).unwrap();
+        fs::create_dir_all(templates.join("tags")).unwrap();
+        fs::write(content.join("a.md"), "---\ntitle: A\ntags: [Rust, Web Dev]\n---\n").unwrap();
+        fs::write(content.join("b.md"), "---\ntitle: B\ntaxonomies:\n  tags: [rust]\n  categories: [Notes]\n---\n").unwrap();
+        fs::write(
+            templates.join("base.html"),
+            "{{ title }}:{% for tag in page.taxonomies.tags %} {{ tag.name }}={{ tag.path | safe }}{% endfor %}\
+             |{% for term in taxonomies.tags.terms %} {{ term.slug }}({{ term.page_count }}){% endfor %}",
+        )
+        .unwrap();
+        fs::write(
+            templates.join("tags/list.html"),
+            "{{ title }}:{% for term in taxonomy.terms %} {{ term.name }}{% endfor %}",
+        )
+        .unwrap();
+        fs::write(
+            templates.join("taxonomy_single.html"),
+            "{{ taxonomy.name }}/{{ term.name }}:{% for page in term.pages %} {{ page.title }}{% endfor %}",
+        )
+        .unwrap();
+        fs::write(templates.join("taxonomy_list.html"), "{{ title }}").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            url_style: UrlStyle::Pretty,
+            taxonomies: vec![
+                TaxonomyConfig { name: String::from("tags"), ..TaxonomyConfig::default() },
+                TaxonomyConfig { name: String::from("categories"), ..TaxonomyConfig::default() },
+            ],
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(output.join("a/index.html")).unwrap(),
+            "A: Rust=/tags/rust/ Web Dev=/tags/web-dev/| rust(2) web-dev(1)"
+        );
+        assert_eq!(fs::read_to_string(output.join("tags/index.html")).unwrap(), "tags: Rust Web Dev");
+        assert_eq!(fs::read_to_string(output.join("tags/rust/index.html")).unwrap(), "tags/Rust: A B");
+        assert_eq!(fs::read_to_string(output.join("tags/web-dev/index.html")).unwrap(), "tags/Web Dev: A");
+        assert_eq!(fs::read_to_string(output.join("categories/index.html")).unwrap(), "categories");
+        assert_eq!(fs::read_to_string(output.join("categories/notes/index.html")).unwrap(), "categories/Notes: B");
+
+        // Terms of taxonomies the config doesn't declare are an error.
+        fs::write(content.join("c.md"), "---\ntaxonomies:\n  authors: [me]\n---\n").unwrap();
+        let errors = convert_files(&site).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].stage(), Stage::Parse);
+        assert!(format!("{:#}", errors.0[0].cause()).contains("unknown taxonomy `authors`"), "{}", errors);
+    }
+
+    #[test]
+    fn test_convert_files_writes_feeds() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("blog")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(templates.join("base.html"), "{{ content | safe }}").unwrap();
+        for listing in ["section.html", "taxonomy_list.html", "taxonomy_single.html"] {
+            fs::write(templates.join(listing), "{{ title }}").unwrap();
+        }
+        fs::write(content.join("about.md"), "# About").unwrap();
+        fs::write(content.join("blog/_index.md"), "---
+title: Blog
+generate_feeds: true
+---
+").unwrap();
+        fs::write(
+            content.join("blog/first.md"),
+            "---
+title: First
+date: 2024-01-01
+tags: [rust]
+---
+Intro
+
+<!-- more -->
+
+Rest",
+        )
+        .unwrap();
+        fs::write(content.join("blog/second.md"), "---
+title: Second
+date: 2024-02-01
+---
+All of it").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            base_url: String::from("https://example.com"),
+            title: String::from("Site"),
+            taxonomies: vec![TaxonomyConfig { name: String::from("tags"), feeds: true, ..TaxonomyConfig::default() }],
+            feeds: config::FeedConfig { rss: true, atom: true, ..config::FeedConfig::default() },
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        // Undated pages stay out of the feeds and the newest page comes first.
+        let rss = fs::read_to_string(output.join("rss.xml")).unwrap();
+        // There is no `index.html` to link to, so the site feed links to the site.
+        assert!(rss.contains("<title>Site</title>\n<link>https://example.com/</link>"), "{}", rss);
+        assert!(!rss.contains("About"), "{}", rss);
+        let second = rss.find("<title>Second</title>").unwrap();
+        assert!(second < rss.find("<title>First</title>").unwrap(), "{}", rss);
+        assert!(rss.contains("<description>&lt;p&gt;Intro&lt;/p&gt;\n</description>"), "{}", rss);
+        assert!(rss.contains("<description>&lt;p&gt;All of it&lt;/p&gt;\n</description>"), "{}", rss);
+
+        let atom = fs::read_to_string(output.join("atom.xml")).unwrap();
+        assert!(atom.contains("<summary type=\"html\">&lt;p&gt;Intro&lt;/p&gt;\n</summary>"), "{}", atom);
+        assert!(atom.contains("<content type=\"html\">&lt;p&gt;All of it&lt;/p&gt;\n</content>"), "{}", atom);
+
+        let blog = fs::read_to_string(output.join("blog/atom.xml")).unwrap();
+        assert!(blog.contains("<link href=\"https://example.com/blog/atom.xml\" rel=\"self\""), "{}", blog);
+        assert!(output.join("blog/rss.xml").exists());
+        let tag = fs::read_to_string(output.join("tags/rust/rss.xml")).unwrap();
+        assert!(tag.contains("<title>First</title>") && !tag.contains("<title>Second</title>"), "{}", tag);
+    }
+
+    #[test]
+    fn test_convert_files_skips_unpublished_pages() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("blog/trip")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+        fs::write(templates.join("section.html"), "{% for page in section.pages %}{{ page.slug }} {% endfor %}").unwrap();
+        fs::write(content.join("blog/_index.md"), "---
+title: Blog
+---
+").unwrap();
+        fs::write(content.join("blog/post.md"), "---
+date: 2024-03-09
+---
+").unwrap();
+        fs::write(content.join("blog/hidden.md"), "---
+date: 2024-03-09
+unlisted: true
+---
+").unwrap();
+        fs::write(content.join("blog/later.md"), "---
+publish_date: 2999-01-01
+---
+").unwrap();
+        fs::write(content.join("blog/gone.md"), "---
+expiry_date: 2000-01-01
+---
+").unwrap();
+        fs::write(content.join("blog/trip/index.md"), "---
+date: 2024-03-10
+draft: true
+---
+").unwrap();
+        fs::write(content.join("blog/trip/photo.jpg"), "jpg").unwrap();
+
+        let mut site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            base_url: String::from("https://example.com"),
+            url_style: UrlStyle::Pretty,
+            feeds: config::FeedConfig { rss: true, ..config::FeedConfig::default() },
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        assert_eq!(fs::read_to_string(output.join("blog/index.html")).unwrap(), "post ");
+        assert!(output.join("blog/hidden/index.html").exists());
+        for skipped in ["blog/later", "blog/gone", "blog/trip"] {
+            assert!(!output.join(skipped).exists(), "{} was built", skipped);
+        }
+        let rss = fs::read_to_string(output.join("rss.xml")).unwrap();
+        assert!(!rss.contains("hidden"), "{}", rss);
+        let sitemap = fs::read_to_string(output.join("sitemap.xml")).unwrap();
+        assert!(!sitemap.contains("hidden"), "{}", sitemap);
+
+        site.drafts = true;
+        site.future = true;
+        convert_files(&site).unwrap();
+
+        assert_eq!(fs::read_to_string(output.join("blog/index.html")).unwrap(), "gone later post trip ");
+        assert!(output.join("blog/trip/photo.jpg").exists());
+        // Drafts are built, but never syndicated.
+        let rss = fs::read_to_string(output.join("rss.xml")).unwrap();
+        assert!(rss.contains("/blog/post/") && !rss.contains("/blog/trip/"), "{}", rss);
+    }
+
+    #[test]
+    fn test_publishing_flags() {
+        let site = load_site(&Cli::parse_from(["test", "build", "--drafts", "--future"])).unwrap();
+        assert!(site.drafts && site.future);
+
+        let site = load_site(&Cli::parse_from(["test"])).unwrap();
+        assert!(!site.drafts && !site.future);
+    }
+
+    #[test]
+    fn test_convert_files_builds_translations() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let i18n = dir.path().join("i18n");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("docs")).unwrap();
+        fs::create_dir_all(content.join("ja/docs")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::create_dir_all(&i18n).unwrap();
+        fs::write(
+            templates.join("base.html"),
+            "{{ lang }} {{ trans(key=\"hello\", lang=lang) }}:{% for t in page.translations %} {{ t.lang }}={{ t.path | safe }}{% endfor %}",
+        )
+        .unwrap();
+        fs::write(templates.join("section.html"), "{{ section.title }}:{% for page in section.pages %} {{ page.path | safe }}{% endfor %}").unwrap();
+        fs::write(i18n.join("en.toml"), "hello = \"Hello\"").unwrap();
+        fs::write(i18n.join("de.toml"), "hello = \"Hallo\"").unwrap();
+        fs::write(i18n.join("ja.toml"), "hello = \"こんにちは\"").unwrap();
+        fs::write(content.join("docs/_index.md"), "---\ntitle: Docs\n---\n").unwrap();
+        fs::write(content.join("docs/_index.de.md"), "---\ntitle: Doku\n---\n").unwrap();
+        fs::write(content.join("docs/intro.md"), "---\ndate: 2024-03-09\n---\n").unwrap();
+        fs::write(content.join("docs/intro.de.md"), "---\ndate: 2024-03-09\n---\n").unwrap();
+        fs::write(content.join("ja/docs/intro.md"), "---\ndate: 2024-03-09\n---\n").unwrap();
+        fs::write(content.join("docs/setup.md"), "").unwrap();
+
+        let config = Config::parse("[languages.de]\ntitle = \"Seite\"\n[languages.ja]\n[feeds]\nrss = true").unwrap();
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            i18n_dir: i18n.display().to_string(),
+            output_dir: output.display().to_string(),
+            base_url: String::from("https://example.com"),
+            url_style: UrlStyle::Pretty,
+            ..config
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        let translations = " en=/docs/intro/ de=/de/docs/intro/ ja=/ja/docs/intro/";
+        assert_eq!(fs::read_to_string(output.join("docs/intro/index.html")).unwrap(), format!("en Hello:{}", translations));
+        assert_eq!(fs::read_to_string(output.join("de/docs/intro/index.html")).unwrap(), format!("de Hallo:{}", translations));
+        assert_eq!(fs::read_to_string(output.join("ja/docs/intro/index.html")).unwrap(), format!("ja こんにちは:{}", translations));
+        assert_eq!(fs::read_to_string(output.join("docs/setup/index.html")).unwrap(), "en Hello: en=/docs/setup/");
+
+        // Every language lists its own pages.
+        assert_eq!(fs::read_to_string(output.join("docs/index.html")).unwrap(), "Docs: /docs/intro/ /docs/setup/");
+        assert_eq!(fs::read_to_string(output.join("de/docs/index.html")).unwrap(), "Doku: /de/docs/intro/");
+        assert_eq!(fs::read_to_string(output.join("ja/docs/index.html")).unwrap(), "docs: /ja/docs/intro/");
+
+        let sitemap = fs::read_to_string(output.join("sitemap.xml")).unwrap();
+        assert!(sitemap.contains(
+            "<loc>https://example.com/de/docs/intro/</loc>\n\
+             <xhtml:link rel=\"alternate\" hreflang=\"en\" href=\"https://example.com/docs/intro/\"/>\n\
+             <xhtml:link rel=\"alternate\" hreflang=\"de\" href=\"https://example.com/de/docs/intro/\"/>\n\
+             <xhtml:link rel=\"alternate\" hreflang=\"ja\" href=\"https://example.com/ja/docs/intro/\"/>\n"
+        ), "{}", sitemap);
+
+        let rss = fs::read_to_string(output.join("de/rss.xml")).unwrap();
+        assert!(rss.contains("<title>Seite</title>\n<link>https://example.com/de/</link>"), "{}", rss);
+        assert!(rss.contains("<language>de</language>"), "{}", rss);
+        assert!(rss.contains("hreflang=\"ja\""), "{}", rss);
+        let rss = fs::read_to_string(output.join("rss.xml")).unwrap();
+        assert!(!rss.contains("<link>https://example.com/de/docs/intro/</link>"), "{}", rss);
+    }
+
+    #[test]
+    fn test_convert_files_writes_sitemap() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("blog")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+        fs::write(templates.join("section.html"), "{{ title }}").unwrap();
+        fs::write(content.join("blog/_index.md"), "---\ntitle: Blog\n---\n").unwrap();
+        fs::write(
+            content.join("blog/post.md"),
+            "---\ndate: 2024-03-09\nchangefreq: yearly\npriority: 0.3\n---\n",
+        )
+        .unwrap();
+        fs::write(content.join("blog/draft.md"), "---\ndraft: true\n---\n").unwrap();
+        fs::write(content.join("hidden.md"), "---\nunlisted: true\n---\n").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            base_url: String::from("https://example.com/"),
+            url_style: UrlStyle::Pretty,
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        let sitemap = fs::read_to_string(output.join("sitemap.xml")).unwrap();
+        assert!(sitemap.contains(
+            "<url>\n<loc>https://example.com/blog/post/</loc>\n<lastmod>2024-03-09T00:00:00Z</lastmod>\n\
+             <changefreq>yearly</changefreq>\n<priority>0.3</priority>\n</url>"
+        ), "{}", sitemap);
+        assert!(sitemap.contains("<loc>https://example.com/blog/</loc>\n<lastmod>2024-03-09T00:00:00Z</lastmod>"), "{}", sitemap);
+        assert!(!sitemap.contains("draft") && !sitemap.contains("hidden"), "{}", sitemap);
+        // Unlisted pages are still built.
+        assert!(output.join("hidden/index.html").exists());
+        assert_eq!(
+            fs::read_to_string(output.join("robots.txt")).unwrap(),
+            "User-agent: *\nAllow: /\n\nSitemap: https://example.com/sitemap.xml\n"
+        );
+
+        // A `robots.txt` template replaces the default one.
+        fs::write(templates.join("robots.txt"), "User-agent: *\nDisallow: /drafts/\nSitemap: {{ sitemap }}\n").unwrap();
+        let site = Site::new(site.config.clone()).unwrap();
+        convert_files(&site).unwrap();
+        assert_eq!(
+            fs::read_to_string(output.join("robots.txt")).unwrap(),
+            "User-agent: *\nDisallow: /drafts/\nSitemap: https://example.com/sitemap.xml\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_files_exposes_toc() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(&content).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(
+            templates.join("base.html"),
+            "{% for h in page.toc %}{{ h.id }}({% for c in h.children %}{{ c.level }}:{{ c.title }}{% endfor %}){% endfor %}|{{ content | safe }}",
+        )
+        .unwrap();
+        fs::write(content.join("guide.md"), "# Guide\n\n## Install {#setup}\n").unwrap();
+        fs::write(content.join("short.md"), "---\ntoc: false\n---\n# Short\n").unwrap();
+
+        let mut config = Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            ..Config::default()
+        };
+        config.markdown.heading_anchors = true;
+        convert_files(&Site::new(config).unwrap()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(output.join("guide.html")).unwrap(),
+            "guide(2:Install)|<h1 id=\"guide\">Guide <a class=\"anchor\" href=\"#guide\" aria-hidden=\"true\">#</a></h1>\n\
+             <h2 id=\"setup\">Install <a class=\"anchor\" href=\"#setup\" aria-hidden=\"true\">#</a></h2>\n"
+        );
+        assert!(fs::read_to_string(output.join("short.html")).unwrap().starts_with("|<h1 id=\"short\">"));
+    }
+
+    #[test]
+    fn test_convert_files_renders_shortcodes() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(&content).unwrap();
+        fs::create_dir_all(templates.join("shortcodes")).unwrap();
+        fs::write(templates.join("base.html"), "{{ content | safe }}").unwrap();
+        fs::write(templates.join("shortcodes/figure.html"), "<figure><img src=\"{{ src }}\"></figure>").unwrap();
+        fs::write(content.join("photo.md"), "---\ntitle: Photo\n---\n{{ figure(src=\"cat.png\") }}\n").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+        assert_eq!(fs::read_to_string(output.join("photo.html")).unwrap(), "<figure><img src=\"cat.png\"></figure>\n");
+
+        // Errors name the file and count lines from the top of it, front matter included.
+        fs::write(content.join("photo.md"), "---\ntitle: Photo\n---\nIntro\n\n{{ figur(src=\"cat.png\") }}\n").unwrap();
+        let errors = convert_files(&site).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].file(), content.join("photo.md"));
+        assert!(format!("{:#}", errors.0[0].cause()).starts_with("in the shortcode on line 6: unknown shortcode `figur`"), "{}", errors);
+    }
+
+    #[test]
+    fn test_convert_files_resolves_links() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("docs")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(templates.join("base.html"), "{{ content | safe }}").unwrap();
+        fs::write(content.join("docs/intro.md"), "# Intro\n\n## Setup\n").unwrap();
+        fs::write(content.join("docs/guide.md"), "See [setup](intro.md#setup) and [home](@/index.md).\n").unwrap();
+        fs::write(content.join("index.md"), "# Home\n").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            base_url: String::from("https://example.com"),
+            url_style: UrlStyle::Pretty,
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+        assert_eq!(
+            fs::read_to_string(output.join("docs/guide/index.html")).unwrap(),
+            "<p>See <a href=\"https://example.com/docs/intro/#setup\">setup</a> and \
+             <a href=\"https://example.com/\">home</a>.</p>\n"
+        );
+
+        fs::write(content.join("docs/guide.md"), "---\ntitle: Guide\n---\n\nSee [setup](intro.md#install).\n").unwrap();
+        let errors = convert_files(&site).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].file(), content.join("docs/guide.md"));
+        assert_eq!(
+            format!("{:#}", errors.0[0].cause()),
+            "on line 5: broken link to `intro.md#install`, docs/intro.md has no heading with the id `install`"
+        );
+    }
+
+    #[test]
+    fn test_convert_files_paginates_listings() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("blog")).unwrap();
+        fs::create_dir_all(templates.join("blog")).unwrap();
+        fs::write(content.join("blog/_index.md"), "+++\npaginate_by = 2\n+++\n").unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(content.join(format!("blog/{}.md", name)), format!("---\ntags: [rust]\n---\n# {}", name)).unwrap();
+        }
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+        let listing = "{{ paginator.current_index }}/{{ paginator.number_pagers }} of {{ paginator.total_pages }}:\
+                       {% for page in paginator.pages %} {{ page.title }}{% endfor %}\
+                       |{{ paginator.previous | default(value=\"-\") | safe }}|{{ paginator.next | default(value=\"-\") | safe }}\
+                       |{{ paginator.first | safe }}|{{ paginator.last | safe }}";
+        fs::write(templates.join("blog/section.html"), listing).unwrap();
+        fs::write(templates.join("taxonomy_single.html"), listing).unwrap();
+        fs::write(templates.join("taxonomy_list.html"), "").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            url_style: UrlStyle::Pretty,
+            taxonomies: vec![TaxonomyConfig { name: String::from("tags"), paginate_by: Some(1), ..TaxonomyConfig::default() }],
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(output.join("blog/index.html")).unwrap(),
+            "1/2 of 3: a b|-|/blog/page/2/|/blog/|/blog/page/2/"
+        );
+        assert_eq!(
+            fs::read_to_string(output.join("blog/page/2/index.html")).unwrap(),
+            "2/2 of 3: c|/blog/|-|/blog/|/blog/page/2/"
+        );
+        assert!(fs::read_to_string(output.join("blog/page/1/index.html")).unwrap().contains("url=/blog/"));
+        assert!(!output.join("blog/page/3").exists());
+
+        assert_eq!(
+            fs::read_to_string(output.join("tags/rust/page/3/index.html")).unwrap(),
+            "3/3 of 3: c|/tags/rust/page/2/|-|/tags/rust/|/tags/rust/page/3/"
+        );
+    }
+
+    #[test]
+    fn test_section_without_template() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let output = dir.path().join("output");
+        fs::create_dir_all(content.join("blog")).unwrap();
+        fs::create_dir_all(content.join("docs")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(content.join("blog/post.md"), "# Post").unwrap();
+        fs::write(content.join("docs/_index.md"), "# Docs").unwrap();
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: output.display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        let errors = convert_files(&site).unwrap_err();
+
+        // Only the section that asked for an index page with its `_index.md` fails.
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].stage(), Stage::Render);
+        assert!(errors.0[0].file().ends_with("docs/_index.md"));
+        assert!(errors.0[0].cause().to_string().contains("docs/section.html, section.html"));
+        assert!(!output.join("index.html").exists());
+        assert!(!output.join("blog/index.html").exists());
+    }
+
+    #[test]
+    fn test_convert_files_pretty_urls() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(content.join("blog")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(content.join("about.md"), "# About").unwrap();
+        fs::write(content.join("blog/first.md"), "---\ndate: 2024-02-03\n---\n# First").unwrap();
+        fs::write(templates.join("base.html"), "{{ page.path | safe }} {{ page.permalink | safe }}").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: dir.path().join("output").display().to_string(),
+            base_url: String::from("https://example.com"),
+            url_style: UrlStyle::Pretty,
+            permalinks: [(String::from("blog"), String::from("/:year/:month/:slug/"))].into(),
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("output/about/index.html")).unwrap(),
+            "/about/ https://example.com/about/"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("output/2024/02/first/index.html")).unwrap(),
+            "/2024/02/first/ https://example.com/2024/02/first/"
+        );
+    }
+
+    #[test]
+    fn test_convert_files_copies_static_and_page_resources() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let static_dir = dir.path().join("static");
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(content.join("blog/trip")).unwrap();
+        fs::create_dir_all(static_dir.join("css")).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(content.join("blog/trip/index.md"), "---\ndate: 2024-07-01\n---\n# Trip").unwrap();
+        fs::write(content.join("blog/trip/beach.jpg"), "jpg").unwrap();
+        fs::write(content.join("blog/notes.pdf"), "pdf").unwrap();
+        fs::write(static_dir.join("css/site.css"), "css").unwrap();
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            static_dir: static_dir.display().to_string(),
+            output_dir: dir.path().join("output").display().to_string(),
+            url_style: UrlStyle::Pretty,
+            permalinks: [(String::from("blog"), String::from("/blog/:year/:slug/"))].into(),
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+
+        let output = dir.path().join("output");
+        assert!(output.join("blog/2024/trip/index.html").exists());
+        assert_eq!(fs::read_to_string(output.join("blog/2024/trip/beach.jpg")).unwrap(), "jpg");
+        assert_eq!(fs::read_to_string(output.join("blog/notes.pdf")).unwrap(), "pdf");
+        assert_eq!(fs::read_to_string(output.join("css/site.css")).unwrap(), "css");
+    }
+
+    #[test]
+    fn test_convert_files_makes_responsive_images() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let static_dir = dir.path().join("static");
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(content.join("blog/trip")).unwrap();
+        fs::create_dir_all(&static_dir).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        image::RgbImage::new(40, 20).save(content.join("blog/trip/beach.png")).unwrap();
+        image::RgbImage::new(10, 10).save(static_dir.join("logo.png")).unwrap();
+        fs::write(
+            content.join("blog/trip/index.md"),
+            "---\ntitle: Trip\n---\n![The beach](beach.png)\n\n![Logo](/logo.png) ![Remote](https://example.com/a.png)",
+        )
+        .unwrap();
+        fs::write(content.join("blog/broken.md"), "Intro\n\n![Missing](missing.png)").unwrap();
+        fs::write(
+            templates.join("base.html"),
+            "{% set logo = resize_image(path=\"logo.png\") %}{{ logo.src | safe }} {{ logo.width }}x{{ logo.height }}\n{{ content | safe }}",
+        )
+        .unwrap();
+
+        let config = Config::parse(&format!(
+            "[images]\nmarkdown = true\nwidths = [16, 64]\nformats = [\"webp\"]\ncache_dir = \"{}\"",
+            dir.path().join("cache").display()
+        ))
+        .unwrap();
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            static_dir: static_dir.display().to_string(),
+            output_dir: dir.path().join("output").display().to_string(),
+            url_style: UrlStyle::Pretty,
+            base_url: String::from("https://example.com/docs/"),
+            ..config
+        })
+        .unwrap();
+        let errors = convert_files(&site).unwrap_err();
+
+        // A Markdown image that isn't there is an error, like a broken link.
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].file(), content.join("blog/broken.md"));
+        assert!(format!("{:#}", errors.0[0].cause()).contains("on line 3: failed to read"), "{}", errors);
+
+        let output = dir.path().join("output");
+        let html = fs::read_to_string(output.join("blog/trip/index.html")).unwrap();
+        let (logo, html) = html.split_once('\n').unwrap();
+        // The images are where `base_url` puts the site, not at the root of the host.
+        let images = "https://example.com/docs/processed_images";
+        assert!(logo.starts_with(&format!("{}/logo-", images)) && logo.ends_with("-10.png 10x10"), "{}", logo);
+        assert!(html.contains(&format!("<picture><source type=\"image/webp\" srcset=\"{}/beach-", images)), "{}", html);
+        assert!(html.contains(&format!("-16.png 16w, {}/beach-", images)), "{}", html);
+        assert!(html.contains("width=\"40\" height=\"20\" alt=\"The beach\"></picture>"), "{}", html);
+        assert!(html.contains("alt=\"Logo\"></picture>"), "{}", html);
+        assert!(html.contains("<img src=\"https://example.com/a.png\" alt=\"Remote\" />"), "{}", html);
+        // Both widths in both formats, plus the logo in both formats.
+        assert_eq!(fs::read_dir(output.join("processed_images")).unwrap().count(), 6);
+    }
+
+    #[test]
+    fn test_convert_files_detects_collisions() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir_all(content.join("about")).unwrap();
+        fs::write(content.join("about.md"), "# About").unwrap();
+        fs::write(content.join("about/index.md"), "# Also about").unwrap();
+
+        let site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            output_dir: dir.path().join("output").display().to_string(),
+            url_style: UrlStyle::Pretty,
+            ..Config::default()
+        })
+        .unwrap();
+        let error = convert_files(&site).unwrap_err().to_string();
+
+        assert!(error.contains("about.md"), "{}", error);
+        assert!(error.contains("index.md"), "{}", error);
+        assert!(!dir.path().join("output").exists());
+    }
+
 
     #[test]
     fn test_with_arguments() {
@@ -248,6 +2101,337 @@ mod tests {
 
         assert_eq!(cli.content, None);
         assert_eq!(cli.output, None);
+        assert_eq!(cli.config, None);
+        assert_eq!(cli.command, None);
+    }
+
+    #[test]
+    fn test_watch_subcommand() {
+        let cli = Cli::parse_from(["test", "watch", "--content", "./my_content"]);
+
+        assert_eq!(cli.command, Some(Command::Watch));
+        assert_eq!(cli.content, Some("./my_content".to_string()));
+    }
+
+    #[test]
+    fn test_theme_css_subcommand() {
+        let cli = Cli::parse_from(["test", "theme-css", "--theme", "InspiredGitHub", "static/code.css"]);
+
+        assert_eq!(
+            cli.command,
+            Some(Command::ThemeCss { theme: Some(String::from("InspiredGitHub")), file: Some(PathBuf::from("static/code.css")) })
+        );
+    }
+
+    #[test]
+    fn test_check_subcommand() {
+        let cli = Cli::parse_from(["test", "check", "--format", "json"]);
+        assert_eq!(cli.command, Some(Command::Check { format: ReportFormat::Json }));
+
+        let cli = Cli::parse_from(["test", "check"]);
+        assert_eq!(cli.command, Some(Command::Check { format: ReportFormat::Human }));
+    }
+
+    #[test]
+    fn test_check_links_fails_on_broken_links() {
+        let dir = tempdir().unwrap();
+        let output_dir = dir.path().join("public");
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("index.html"), "<a href=\"/about/\">About</a>").unwrap();
+        let config_path = dir.path().join("site.toml");
+        fs::write(&config_path, "output_dir = \"public\"").unwrap();
+
+        let cli = Cli::parse_from(["test", "--config", config_path.to_str().unwrap(), "check"]);
+        assert_eq!(check_links(&cli, ReportFormat::Human).unwrap_err().to_string(), "found 1 broken links");
+
+        fs::create_dir_all(output_dir.join("about")).unwrap();
+        fs::write(output_dir.join("about/index.html"), "<p>About</p>").unwrap();
+        assert!(check_links(&cli, ReportFormat::Json).is_ok());
+    }
+
+    #[test]
+    fn test_load_templates_reports_errors() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("base.html"), "{{ unclosed").unwrap();
+
+        let error = load_templates(&format!("{}/*.html", dir.path().display())).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("base.html"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_parallel_build_matches_sequential_build() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(&templates).unwrap();
+        for section in ["blog", "docs", "notes"] {
+            fs::create_dir_all(content.join(section)).unwrap();
+            for number in 0..20 {
+                fs::write(
+                    content.join(section).join(format!("page-{}.md", number)),
+                    format!("---\ntitle: {} {}\n---\n# Heading\n\nSome *text* for page {}.", section, number, number),
+                )
+                .unwrap();
+            }
+        }
+        fs::write(templates.join("base.html"), "<title>{{ title }}</title>{{ content | safe }}{{ page.path }}").unwrap();
+
+        let build = |jobs: usize, output: &str| {
+            let site = Site::new(Config {
+                content_dir: content.display().to_string(),
+                template_dir: templates.display().to_string(),
+                output_dir: dir.path().join(output).display().to_string(),
+                ..Config::default()
+            })
+            .unwrap();
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build().unwrap();
+            pool.install(|| convert_files(&site)).unwrap();
+        };
+        build(1, "sequential");
+        build(8, "parallel");
+
+        let files = |output: &str| -> Vec<(PathBuf, Vec<u8>)> {
+            let root = dir.path().join(output);
+            WalkDir::new(&root)
+                .sort_by_file_name()
+                .into_iter()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| (entry.path().strip_prefix(&root).unwrap().to_path_buf(), fs::read(entry.path()).unwrap()))
+                .collect()
+        };
+        // Every page, plus the sitemap and robots.txt.
+        assert_eq!(files("sequential").len(), 62);
+        assert_eq!(files("sequential"), files("parallel"));
+    }
+
+    #[test]
+    fn test_jobs_flag() {
+        assert_eq!(Cli::parse_from(["test", "--jobs", "4"]).jobs, Some(4));
+        assert_eq!(Cli::parse_from(["test", "serve", "-j", "2"]).jobs, Some(2));
+    }
+
+    #[test]
+    fn test_convert_files_reports_every_error() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(&content).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(content.join("a-bad.md"), "---\ntitle: [unclosed\n---\n").unwrap();
+        fs::write(content.join("b-good.md"), "# Good").unwrap();
+        fs::write(content.join("c-broken.md"), "---\ntitle: Broken\nextra:\n  boom: true\n---\n").unwrap();
+        fs::write(
+            templates.join("base.html"),
+            "{% if page.extra.boom %}{{ missing_variable }}{% endif %}{{ title }}",
+        )
+        .unwrap();
+
+        let mut site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: dir.path().join("output").display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let errors = convert_files(&site).unwrap_err();
+        assert_eq!(errors.0.len(), 2);
+        assert_eq!(errors.0[0].stage(), Stage::Parse);
+        assert!(errors.0[0].file().ends_with("a-bad.md"));
+        assert_eq!(errors.0[1].stage(), Stage::Render);
+        assert!(errors.0[1].file().ends_with("c-broken.md"));
+        assert!(format!("{:#}", errors.0[1].cause()).contains("missing_variable"), "{}", errors);
+        // The pages that were fine are still built.
+        assert_eq!(fs::read_to_string(dir.path().join("output/b-good.html")).unwrap(), "Good");
+
+        site.fail_fast = true;
+        let errors = convert_files(&site).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].stage(), Stage::Parse);
+    }
+
+    #[test]
+    fn test_fail_fast_flag() {
+        assert!(!Cli::parse_from(["test"]).fail_fast);
+        assert!(Cli::parse_from(["test", "--fail-fast"]).fail_fast);
+    }
+
+    #[test]
+    fn test_serve_subcommand() {
+        let cli = Cli::parse_from(["test", "serve", "--port", "3000"]);
+
+        assert_eq!(cli.command, Some(Command::Serve { port: 3000, interface: String::from("127.0.0.1") }));
+
+        let site = load_site(&cli).unwrap();
+        assert_eq!(site.config.base_url, "http://127.0.0.1:3000");
+        assert!(site.live_reload);
+        assert!(!load_site(&Cli::parse_from(["test", "watch"])).unwrap().live_reload);
+
+        let everywhere = Cli::parse_from(["test", "serve", "--interface", "0.0.0.0"]);
+        assert_eq!(load_site(&everywhere).unwrap().config.base_url, "http://localhost:8000");
+        let everywhere = Cli::parse_from(["test", "serve", "--interface", "::"]);
+        assert_eq!(load_site(&everywhere).unwrap().config.base_url, "http://localhost:8000");
+        let loopback = Cli::parse_from(["test", "serve", "--interface", "::1"]);
+        assert_eq!(load_site(&loopback).unwrap().config.base_url, "http://[::1]:8000");
+    }
+
+    #[test]
+    fn test_convert_file_to_html_injects_live_reload() {
+        let dir = tempdir().unwrap();
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(templates.join("base.html"), "<html><body>{{ content | safe }}</body></html>").unwrap();
+        fs::write(dir.path().join("page.md"), "Hello").unwrap();
+
+        let mut site = Site::new(Config {
+            content_dir: dir.path().display().to_string(),
+            template_dir: templates.display().to_string(),
+            output_dir: dir.path().join("output").display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        site.live_reload = true;
+
+        let page = load_page(&site, &dir.path().join("page.md").display().to_string()).unwrap();
+        convert_file_to_html(&site, &Content::default(), &page).unwrap();
+
+        let html = fs::read_to_string(dir.path().join("output/page.html")).unwrap();
+        assert!(html.starts_with("<html><body><p>Hello</p>\n<script>"), "{}", html);
+        assert!(html.contains("/__livereload"), "{}", html);
+        assert!(html.ends_with("</body></html>"), "{}", html);
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        let templates = dir.path().join("templates");
+        let static_dir = dir.path().join("static");
+        let output = dir.path().join("output");
+        fs::create_dir_all(&content).unwrap();
+        fs::create_dir_all(&templates).unwrap();
+        fs::create_dir_all(&static_dir).unwrap();
+        fs::write(content.join("a.md"), "# A").unwrap();
+        fs::write(content.join("b.md"), "# B").unwrap();
+        fs::write(templates.join("base.html"), "{{ title }}").unwrap();
+        let sass_dir = dir.path().join("sass");
+        fs::create_dir_all(&sass_dir).unwrap();
+        fs::write(sass_dir.join("_colors.scss"), "$text: red;").unwrap();
+        fs::write(sass_dir.join("style.scss"), "@import \"colors\";\np { color: $text; }").unwrap();
+
+        let cli = Cli::parse_from(["test", "watch", "--config", dir.path().join("rusty_ssg.toml").to_str().unwrap()]);
+        let mut site = Site::new(Config {
+            content_dir: content.display().to_string(),
+            template_dir: templates.display().to_string(),
+            static_dir: static_dir.display().to_string(),
+            output_dir: output.display().to_string(),
+            sass_dir: sass_dir.display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        convert_files(&site).unwrap();
+        assert_eq!(fs::read_to_string(output.join("style.css")).unwrap(), "p {\n  color: red;\n}\n");
+
+        // Editing one page only rewrites that page.
+        fs::write(content.join("a.md"), "# A2").unwrap();
+        fs::write(content.join("b.md"), "# B2").unwrap();
+        rebuild(&cli, &mut site, &[content.join("a.md")]).unwrap();
+        assert_eq!(fs::read_to_string(output.join("a.html")).unwrap(), "A2");
+        assert_eq!(fs::read_to_string(output.join("b.html")).unwrap(), "B");
+
+        // A static file is just copied.
+        fs::write(static_dir.join("site.css"), "css").unwrap();
+        rebuild(&cli, &mut site, &[static_dir.join("site.css")]).unwrap();
+        assert_eq!(fs::read_to_string(output.join("site.css")).unwrap(), "css");
+        assert_eq!(fs::read_to_string(output.join("b.html")).unwrap(), "B");
+
+        // A partial recompiles the stylesheets importing it, and only them.
+        fs::write(sass_dir.join("_colors.scss"), "$text: blue;").unwrap();
+        rebuild(&cli, &mut site, &[sass_dir.join("_colors.scss")]).unwrap();
+        assert_eq!(fs::read_to_string(output.join("style.css")).unwrap(), "p {\n  color: blue;\n}\n");
+        assert_eq!(fs::read_to_string(output.join("b.html")).unwrap(), "B");
+        fs::write(sass_dir.join("_colors.scss"), "$text: $blue;").unwrap();
+        let error = rebuild(&cli, &mut site, &[sass_dir.join("_colors.scss")]).unwrap_err();
+        assert!(format!("{:?}", error).contains("_colors.scss on line 1"), "{:?}", error);
+        fs::write(sass_dir.join("_colors.scss"), "$text: blue;").unwrap();
+
+        // A template change rebuilds everything.
+        fs::write(templates.join("base.html"), "<{{ title }}>").unwrap();
+        rebuild(&cli, &mut site, &[templates.join("base.html")]).unwrap();
+        assert_eq!(fs::read_to_string(output.join("a.html")).unwrap(), "<A2>");
+        assert_eq!(fs::read_to_string(output.join("b.html")).unwrap(), "<B2>");
+
+        // Files outside the site are ignored.
+        fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        rebuild(&cli, &mut site, &[dir.path().join("notes.txt")]).unwrap();
+    }
+
+    #[test]
+    fn test_load_and_build_recovers_from_broken_templates() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("content")).unwrap();
+        fs::create_dir_all(dir.path().join("templates")).unwrap();
+        fs::write(dir.path().join("content/a.md"), "# A").unwrap();
+        fs::write(dir.path().join("templates/base.html"), "{{ title").unwrap();
+        let config_file = dir.path().join("rusty_ssg.toml");
+        fs::write(&config_file, "").unwrap();
+        let cli = Cli::parse_from(["test", "watch", "--config", config_file.to_str().unwrap()]);
+
+        // The error is reported like a failed build, and the next change loads the site again.
+        let mut site = None;
+        assert!(load_and_build(&cli, &mut site).is_err());
+        assert!(site.is_none());
+        assert_eq!(Path::new(&watched_config(&cli).content_dir), dir.path().join("content"));
+
+        fs::write(dir.path().join("templates/base.html"), "{{ title }}").unwrap();
+        load_and_build(&cli, &mut site).unwrap();
+        assert!(site.is_some());
+        assert_eq!(fs::read_to_string(dir.path().join("output/a.html")).unwrap(), "A");
+    }
+
+    #[test]
+    fn test_relative_to() {
+        let dir = tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir_all(content.join("blog")).unwrap();
+
+        assert_eq!(relative_to(&content.join("blog/post.md"), &content), Some(PathBuf::from("blog/post.md")));
+        assert_eq!(relative_to(&content, &content), Some(PathBuf::new()));
+        assert_eq!(relative_to(&dir.path().join("other.md"), &content), None);
+    }
+
+    #[test]
+    fn test_cli_flags_override_config() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("site.toml");
+        fs::write(&config_path, "content_dir = \"pages\"\noutput_dir = \"public\"\ntitle = \"Site\"").unwrap();
+
+        let cli = Cli::parse_from(["test", "--config", config_path.to_str().unwrap(), "--output", "./elsewhere"]);
+        let config = load_config(&cli).unwrap();
+
+        assert_eq!(Path::new(&config.content_dir), dir.path().join("pages"));
+        assert_eq!(config.output_dir, "./elsewhere");
+        assert_eq!(config.title, "Site");
+    }
+
+    #[test]
+    fn test_render_page_exposes_config() {
+        let template_dir = tempdir().unwrap();
+        fs::write(template_dir.path().join("base.html"), "{{ config.title }} by {{ config.author }}").unwrap();
+
+        let site = Site::new(Config {
+            title: String::from("My Site"),
+            author: Some(String::from("Me")),
+            template_dir: template_dir.path().display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let rendered = render_page(&site, &Content::default(), &test_page(PageMeta::default()), "", &[]).unwrap();
+
+        assert_eq!(rendered, "My Site by Me");
     }
 
     #[test]
@@ -268,17 +2452,31 @@ mod tests {
         .unwrap();
 
         // Define SitePaths (adjust to your struct fields)
-        let site_paths = SitePaths {
-            content_path: "tests/content".into(),
-            template_path,
-            base_template: base_template.into(),
-            output_path: "tests/output".into(),
+        let site = Site {
+            paths: SitePaths {
+                content_path: "tests/content".into(),
+                template_path: template_path.clone(),
+                base_template: base_template.into(),
+                output_path: "tests/output".into(),
+                static_path: "tests/static".into(),
+            },
+            config: Config::default(),
+            live_reload: false,
+            fail_fast: false,
+            drafts: false,
+            future: false,
+            tera: load_templates(&template_path).unwrap(),
+            images: Arc::default(),
         };
 
         let html_output = "<h1>Hello</h1><p>World</p>";
+        let page = test_page(PageMeta {
+            title: Some(String::from("The Title")),
+            ..PageMeta::default()
+        });
 
         // Act
-        let rendered = render_page(&site_paths, html_output).unwrap();
+        let rendered = render_page(&site, &Content::default(), &page, html_output, &[]).unwrap();
 
         // Assert
         assert!(rendered.contains("<title>The Title</title>"));
@@ -286,6 +2484,68 @@ mod tests {
         assert!(rendered.contains("<p>World</p>"));
     }
 
+    #[test]
+    fn test_render_page_picks_template() {
+        let template_dir = tempdir().unwrap();
+        fs::create_dir_all(template_dir.path().join("blog")).unwrap();
+        fs::write(template_dir.path().join("base.html"), "base").unwrap();
+        fs::write(template_dir.path().join("post.html"), "post").unwrap();
+        fs::write(template_dir.path().join("blog/page.html"), "blog page").unwrap();
+
+        let site = Site::new(Config {
+            template_dir: template_dir.path().display().to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        let page_in = |section: &str, template: Option<&str>| Page {
+            section: section.to_string(),
+            ..test_page(PageMeta { template: template.map(String::from), ..PageMeta::default() })
+        };
+
+        assert_eq!(render_page(&site, &Content::default(), &page_in("", None), "", &[]).unwrap(), "base");
+        assert_eq!(render_page(&site, &Content::default(), &page_in("blog", None), "", &[]).unwrap(), "blog page");
+        assert_eq!(render_page(&site, &Content::default(), &page_in("blog/2024", None), "", &[]).unwrap(), "blog page");
+        assert_eq!(render_page(&site, &Content::default(), &page_in("blog", Some("post.html")), "", &[]).unwrap(), "post");
+
+        let error = render_page(&site, &Content::default(), &page_in("blog", Some("talk.html")), "", &[]).unwrap_err();
+        assert_eq!(error.to_string(), "template `talk.html` not found, tried: blog/talk.html, talk.html");
+    }
+
+    #[test]
+    fn test_render_page_exposes_page_metadata() {
+        let template_dir = tempdir().unwrap();
+        fs::write(
+            template_dir.path().join("base.html"),
+            "{{ page.title }}|{{ page.date }}|{{ page.tags | join(sep=\",\") }}|{{ page.extra.mood }}",
+        )
+        .unwrap();
+
+        let site = Site {
+            paths: SitePaths {
+                content_path: "tests/content".into(),
+                template_path: format!("{}/*.html", template_dir.path().display()),
+                base_template: "base.html".into(),
+                output_path: "tests/output".into(),
+                static_path: "tests/static".into(),
+            },
+            config: Config::default(),
+            live_reload: false,
+            fail_fast: false,
+            drafts: false,
+            future: false,
+            tera: load_templates(&format!("{}/*.html", template_dir.path().display())).unwrap(),
+            images: Arc::default(),
+        };
+        let (meta, _) = split_front_matter(
+            "---\ntitle: Hi\ndate: 2024-05-01\ntags: [a, b]\nextra:\n  mood: happy\n---\nBody",
+        )
+        .unwrap();
+
+        let rendered = render_page(&site, &Content::default(), &test_page(meta), "", &[]).unwrap();
+
+        assert_eq!(rendered, "Hi|2024-05-01|a,b|happy");
+    }
+
     #[test]
     fn test_create_and_write_file_creates_and_writes() -> io::Result<()> {
         // Arrange: make a temporary directory
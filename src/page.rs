@@ -0,0 +1,502 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, FixedOffset};
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+use serde::Serialize;
+
+use crate::config::{Config, UrlStyle};
+use crate::date::parse_date;
+use crate::front_matter::PageMeta;
+use crate::i18n::{Translation, split_language};
+use crate::slug::slugify;
+use crate::taxonomy::{TermLink, page_terms};
+
+/// A Markdown file from the content directory along with where it ends up.
+#[derive(Debug, Clone, Serialize)]
+pub struct Page {
+    #[serde(flatten)]
+    pub meta: PageMeta,
+    /// The source file relative to the content directory, e.g. `blog/intro.md`.
+    pub relative_path: String,
+    /// `relative_path` without the language, so `blog/intro.de.md` is
+    /// `blog/intro.md`. Translations of a page share it.
+    #[serde(skip)]
+    pub untranslated_path: String,
+    /// The language the page is written in.
+    pub lang: String,
+    /// The page in every language it is written in, itself included.
+    pub translations: Vec<Translation>,
+    /// The directory the page sits in relative to the content directory, e.g. `blog`.
+    pub section: String,
+    pub slug: String,
+    /// The URL of the page relative to the site root, e.g. `/blog/intro/`.
+    pub path: String,
+    /// The full URL of the page, including the configured base URL.
+    pub permalink: String,
+    /// The terms the page has, keyed by taxonomy.
+    pub taxonomies: BTreeMap<String, Vec<TermLink>>,
+    /// The `date` from the front matter, parsed.
+    #[serde(skip)]
+    pub datetime: Option<DateTime<FixedOffset>>,
+    /// The `publish_date` and `expiry_date` from the front matter, parsed.
+    #[serde(skip)]
+    pub publish_datetime: Option<DateTime<FixedOffset>>,
+    #[serde(skip)]
+    pub expiry_datetime: Option<DateTime<FixedOffset>>,
+    #[serde(skip)]
+    pub source: PathBuf,
+    #[serde(skip)]
+    pub output_file: PathBuf,
+    #[serde(skip)]
+    pub markdown: String,
+    /// The line of the source file the Markdown starts on, below the front matter.
+    #[serde(skip)]
+    pub markdown_line: usize,
+}
+
+impl Page {
+    pub fn new(
+        config: &Config,
+        source: &Path,
+        content_dir: &str,
+        mut meta: PageMeta,
+        markdown: &str,
+    ) -> Result<Page> {
+        // Pages outside the content directory just keep their file name.
+        let relative = source
+            .strip_prefix(content_dir)
+            .ok()
+            .or_else(|| source.file_name().map(Path::new))
+            .unwrap_or(source);
+        // `intro.de.md` is the German `intro.md`, and goes where that does under `/de/`.
+        let (lang, untranslated) = split_language(config, relative);
+        let file_stem = untranslated
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let section = untranslated
+            .parent()
+            .map(url_components)
+            .unwrap_or_default();
+
+        // A page bundle (`trip/index.md`) is named after its directory.
+        let default_slug = match section.rsplit('/').next() {
+            Some(directory) if file_stem == "index" && !directory.is_empty() => directory.to_string(),
+            _ => file_stem.clone(),
+        };
+        if meta.title.is_none() {
+            meta.title = Some(default_title(&default_slug, markdown));
+        }
+        // The override goes into the URL, so it gets the same treatment as
+        // headings: `../about` can't climb out of the section.
+        let slug = match &meta.slug {
+            Some(slug) => match slugify(slug) {
+                slugified if slugified.is_empty() => bail!("`slug` {:?} has no letters or digits", slug),
+                slugified => slugified,
+            },
+            None => default_slug,
+        };
+
+        let path = match config.permalink_pattern(&section) {
+            Some(pattern) => expand_permalink(pattern, &meta, &section, &slug, &file_stem)?,
+            None => default_path(config.url_style, &section, &file_stem),
+        };
+        let path = finish_path(config.url_style, config.language_directory(&lang, &path));
+        let permalink = format!("{}{}", config.base_url.trim_end_matches('/'), path);
+        let taxonomies = page_terms(config, &lang, &meta.taxonomies, &meta.tags)?;
+        let datetime = meta.date.as_deref().map(parse_date).transpose()?;
+        let publish_datetime = meta.publish_date.as_deref().map(parse_date).transpose()?;
+        let expiry_datetime = meta.expiry_date.as_deref().map(parse_date).transpose()?;
+        if let (Some(publish), Some(expiry)) = (publish_datetime, expiry_datetime)
+            && expiry <= publish
+        {
+            bail!("`expiry_date` has to be after `publish_date`");
+        }
+        if let Some(priority) = meta.priority
+            && !(0.0..=1.0).contains(&priority)
+        {
+            bail!("`priority` is {}, it has to be between 0.0 and 1.0", priority);
+        }
+
+        Ok(Page {
+            meta,
+            relative_path: url_components(relative),
+            untranslated_path: url_components(&untranslated),
+            lang,
+            translations: Vec::new(),
+            section,
+            slug,
+            path,
+            permalink,
+            taxonomies,
+            datetime,
+            publish_datetime,
+            expiry_datetime,
+            source: source.to_path_buf(),
+            output_file: PathBuf::new(),
+            markdown: markdown.to_string(),
+            markdown_line: 1,
+        })
+    }
+
+    /// Why the page is left out of a build made at `now`, if it is. Drafts are
+    /// built when `drafts` is set, and pages outside their publishing dates when
+    /// `future` is.
+    pub fn excluded(&self, now: DateTime<FixedOffset>, drafts: bool, future: bool) -> Option<Exclusion> {
+        if self.meta.draft && !drafts {
+            Some(Exclusion::Draft)
+        } else if self.publish_datetime.is_some_and(|publish| publish > now) && !future {
+            Some(Exclusion::Scheduled)
+        } else if self.expiry_datetime.is_some_and(|expiry| expiry <= now) && !future {
+            Some(Exclusion::Expired)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the page is the `index.md` of a page bundle (or of the content
+    /// directory), in any language.
+    pub fn is_index(&self) -> bool {
+        Path::new(&self.untranslated_path).file_stem() == Some(OsStr::new("index"))
+    }
+
+    pub fn translation(&self) -> Translation {
+        Translation {
+            lang: self.lang.clone(),
+            title: self.meta.title.clone(),
+            path: self.path.clone(),
+            permalink: self.permalink.clone(),
+        }
+    }
+
+    /// The Markdown above a `<!-- more -->` line, if the page has one.
+    pub fn summary(&self) -> Option<&str> {
+        let mut offset = 0;
+        for line in self.markdown.split_inclusive('\n') {
+            if line.trim() == "<!-- more -->" {
+                return Some(&self.markdown[..offset]);
+            }
+            offset += line.len();
+        }
+        None
+    }
+}
+
+/// Why a page is left out of the build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Exclusion {
+    Draft,
+    /// Its `publish_date` hasn't come yet.
+    Scheduled,
+    /// Its `expiry_date` has passed.
+    Expired,
+}
+
+/// Joins the components of a relative path with `/` whatever platform we're on.
+pub fn url_components(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .filter(|component| component != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// The URL a page gets when no permalink pattern applies to its section.
+fn default_path(url_style: UrlStyle, section: &str, file_stem: &str) -> String {
+    let directory = if section.is_empty() {
+        String::from("/")
+    } else {
+        format!("/{}/", section)
+    };
+
+    match url_style {
+        UrlStyle::Ugly => format!("{}{}.html", directory, file_stem),
+        // An index page already is the directory, so don't nest it another level.
+        UrlStyle::Pretty if file_stem == "index" => directory,
+        UrlStyle::Pretty => format!("{}{}/", directory, file_stem),
+    }
+}
+
+/// The URL of a generated page that stands for a whole directory, such as a
+/// section's index page: `/blog/` with pretty URLs, `/blog/index.html` otherwise.
+pub fn index_path(url_style: UrlStyle, directory: &str) -> String {
+    let directory = directory.trim_matches('/');
+    let directory = if directory.is_empty() {
+        String::from("/")
+    } else {
+        format!("/{}/", directory)
+    };
+
+    match url_style {
+        UrlStyle::Ugly => format!("{}index.html", directory),
+        UrlStyle::Pretty => directory,
+    }
+}
+
+// Fills in the `:name` placeholders of a permalink pattern such as
+// `/blog/:year/:month/:slug/`.
+fn expand_permalink(
+    pattern: &str,
+    meta: &PageMeta,
+    section: &str,
+    slug: &str,
+    file_stem: &str,
+) -> Result<String> {
+    let date_part = |index: usize, token: &str| -> Result<String> {
+        let date = meta.date.as_deref().unwrap_or_default();
+        let parts: Vec<&str> = date.get(..10).unwrap_or_default().split('-').collect();
+        match parts.get(index) {
+            Some(part) if parts.len() == 3 && part.chars().all(|c| c.is_ascii_digit()) => {
+                Ok(part.to_string())
+            }
+            _ => bail!(
+                "permalink `{}` uses `{}` but the page has no `YYYY-MM-DD` date",
+                pattern,
+                token
+            ),
+        }
+    };
+
+    let mut path = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find(':') {
+        path.push_str(&rest[..start]);
+        let token_len = rest[start + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len() - start - 1);
+        let token = &rest[start..start + 1 + token_len];
+        let value = match token {
+            ":year" => date_part(0, token)?,
+            ":month" => date_part(1, token)?,
+            ":day" => date_part(2, token)?,
+            ":slug" => slug.to_string(),
+            ":filename" => file_stem.to_string(),
+            ":section" => section.to_string(),
+            _ => bail!("unknown placeholder `{}` in permalink `{}`", token, pattern),
+        };
+        path.push_str(&value);
+        rest = &rest[start + token.len()..];
+    }
+    path.push_str(rest);
+
+    Ok(path)
+}
+
+// Makes sure a URL starts at the site root and, when it doesn't name a file,
+// ends the way the URL style asks for.
+fn finish_path(url_style: UrlStyle, path: String) -> String {
+    let mut path = format!("/{}", path.trim_start_matches('/'));
+    while path.contains("//") {
+        path = path.replace("//", "/");
+    }
+
+    let last_segment = path.rsplit('/').next().unwrap_or_default();
+    if path.ends_with('/') || last_segment.contains('.') {
+        return path;
+    }
+    match url_style {
+        UrlStyle::Ugly => path + ".html",
+        UrlStyle::Pretty => path + "/",
+    }
+}
+
+/// Pages without a title in their front matter use their first `#` heading, or
+/// failing that the file name.
+pub fn default_title(file_stem: &str, markdown_body: &str) -> String {
+    let mut in_heading = false;
+    let mut title = String::new();
+    for event in Parser::new(markdown_body) {
+        match event {
+            Event::Start(Tag::Heading { level: HeadingLevel::H1, .. }) => in_heading = true,
+            Event::End(TagEnd::Heading(HeadingLevel::H1)) => break,
+            Event::Text(text) | Event::Code(text) if in_heading => title.push_str(&text),
+            _ => {}
+        }
+    }
+
+    if title.trim().is_empty() {
+        file_stem.to_string()
+    } else {
+        title.trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(config: &Config, source: &str, meta: PageMeta) -> Result<Page> {
+        Page::new(config, Path::new(source), "./content", meta, "")
+    }
+
+    #[test]
+    fn test_default_title() {
+        assert_eq!(default_title("post", "Intro\n\n# The `Real` Title\n\n# Second"), "The Real Title");
+        assert_eq!(default_title("post", "## Not a level one heading"), "post");
+    }
+
+    #[test]
+    fn test_ugly_urls() {
+        let config = Config::default();
+
+        let about = page(&config, "./content/about.md", PageMeta::default()).unwrap();
+        assert_eq!(about.path, "/about.html");
+        assert_eq!(about.section, "");
+
+        let intro = page(&config, "./content/blog/intro.md", PageMeta::default()).unwrap();
+        assert_eq!(intro.path, "/blog/intro.html");
+        assert_eq!(intro.section, "blog");
+        assert_eq!(intro.relative_path, "blog/intro.md");
+    }
+
+    #[test]
+    fn test_pretty_urls() {
+        let config = Config {
+            url_style: UrlStyle::Pretty,
+            base_url: String::from("https://example.com/"),
+            ..Config::default()
+        };
+
+        let about = page(&config, "./content/about.md", PageMeta::default()).unwrap();
+        assert_eq!(about.path, "/about/");
+        assert_eq!(about.permalink, "https://example.com/about/");
+
+        let index = page(&config, "./content/index.md", PageMeta::default()).unwrap();
+        assert_eq!(index.path, "/");
+
+        let docs = page(&config, "./content/docs/index.md", PageMeta::default()).unwrap();
+        assert_eq!(docs.path, "/docs/");
+        assert_eq!(docs.slug, "docs");
+    }
+
+    #[test]
+    fn test_permalink_pattern() {
+        let config = Config {
+            url_style: UrlStyle::Pretty,
+            permalinks: BTreeMap::from([(String::from("blog"), String::from("/blog/:year/:month/:slug/"))]),
+            ..Config::default()
+        };
+        let meta = PageMeta {
+            date: Some(String::from("2024-03-09")),
+            ..PageMeta::default()
+        };
+
+        let post = page(&config, "./content/blog/hello-world.md", meta.clone()).unwrap();
+        assert_eq!(post.path, "/blog/2024/03/hello-world/");
+
+        // Nested directories fall back to the pattern of the closest parent.
+        let nested = page(
+            &config,
+            "./content/blog/drafts/post.md",
+            PageMeta { slug: Some(String::from("custom")), ..meta },
+        )
+        .unwrap();
+        assert_eq!(nested.path, "/blog/2024/03/custom/");
+
+        // Other sections keep the default URLs.
+        let doc = page(&config, "./content/docs/intro.md", PageMeta::default()).unwrap();
+        assert_eq!(doc.path, "/docs/intro/");
+    }
+
+    #[test]
+    fn test_slug_override_is_slugified() {
+        let config = Config::default();
+        let slug = |slug: &str| PageMeta { slug: Some(String::from(slug)), ..PageMeta::default() };
+
+        let post = page(&config, "./content/blog/post.md", slug("Hello World")).unwrap();
+        assert_eq!(post.slug, "hello-world");
+
+        // Permalink patterns can't be pointed outside the section.
+        let config = Config {
+            permalinks: BTreeMap::from([(String::from("blog"), String::from("/blog/:slug"))]),
+            ..config
+        };
+        let post = page(&config, "./content/blog/post.md", slug("../../etc/passwd")).unwrap();
+        assert_eq!(post.path, "/blog/etc-passwd.html");
+        assert!(page(&config, "./content/blog/post.md", slug("/")).is_err());
+    }
+
+    #[test]
+    fn test_templates_see_one_slug() {
+        let meta = PageMeta { slug: Some(String::from("Hello World")), ..PageMeta::default() };
+        let post = page(&Config::default(), "./content/blog/post.md", meta).unwrap();
+
+        let json = serde_json::to_string(&post).unwrap();
+        assert_eq!(json.matches("\"slug\":").count(), 1, "{}", json);
+        assert_eq!(tera::to_value(&post).unwrap()["slug"], post.slug.as_str());
+    }
+
+    #[test]
+    fn test_permalink_pattern_errors() {
+        let config = Config {
+            permalinks: BTreeMap::from([
+                (String::from("blog"), String::from("/blog/:year/:slug")),
+                (String::from("notes"), String::from("/:nope/:slug")),
+            ]),
+            ..Config::default()
+        };
+
+        let error = page(&config, "./content/blog/post.md", PageMeta::default()).unwrap_err();
+        assert!(error.to_string().contains(":year"), "{}", error);
+
+        let error = page(&config, "./content/notes/post.md", PageMeta::default()).unwrap_err();
+        assert!(error.to_string().contains(":nope"), "{}", error);
+
+        // Without a trailing slash an ugly URL gets a `.html` file.
+        let meta = PageMeta { date: Some(String::from("2023-12-01")), ..PageMeta::default() };
+        let post = page(&config, "./content/blog/post.md", meta).unwrap();
+        assert_eq!(post.path, "/blog/2023/post.html");
+    }
+
+    #[test]
+    fn test_summary() {
+        let config = Config::default();
+        let post = Page::new(&config, Path::new("./content/post.md"), "./content", PageMeta::default(), "Intro\n\n<!-- more -->\n\nRest").unwrap();
+        assert_eq!(post.summary(), Some("Intro\n\n"));
+        assert_eq!(page(&config, "./content/post.md", PageMeta::default()).unwrap().summary(), None);
+    }
+
+    #[test]
+    fn test_invalid_front_matter_values() {
+        let meta = PageMeta { date: Some(String::from("last tuesday")), ..PageMeta::default() };
+        let error = page(&Config::default(), "./content/post.md", meta).unwrap_err();
+        assert!(error.to_string().contains("`last tuesday` is not a date"), "{}", error);
+
+        let meta = PageMeta { priority: Some(1.5), ..PageMeta::default() };
+        assert!(page(&Config::default(), "./content/post.md", meta).is_err());
+
+        let meta = PageMeta {
+            publish_date: Some(String::from("2024-06-01")),
+            expiry_date: Some(String::from("2024-05-01")),
+            ..PageMeta::default()
+        };
+        assert!(page(&Config::default(), "./content/post.md", meta).is_err());
+    }
+
+    #[test]
+    fn test_excluded() {
+        let config = Config::default();
+        let now = parse_date("2024-06-01T12:00:00Z").unwrap();
+        let excluded = |meta: PageMeta, drafts: bool, future: bool| {
+            page(&config, "./content/post.md", meta).unwrap().excluded(now, drafts, future)
+        };
+
+        assert_eq!(excluded(PageMeta::default(), false, false), None);
+        let draft = PageMeta { draft: true, ..PageMeta::default() };
+        assert_eq!(excluded(draft.clone(), false, false), Some(Exclusion::Draft));
+        assert_eq!(excluded(draft, true, false), None);
+
+        let scheduled = PageMeta { publish_date: Some(String::from("2024-06-02")), ..PageMeta::default() };
+        assert_eq!(excluded(scheduled.clone(), false, false), Some(Exclusion::Scheduled));
+        assert_eq!(excluded(scheduled, false, true), None);
+        let published = PageMeta { publish_date: Some(String::from("2024-06-01")), ..PageMeta::default() };
+        assert_eq!(excluded(published, false, false), None);
+
+        let expired = PageMeta { expiry_date: Some(String::from("2024-06-01T12:00:00Z")), ..PageMeta::default() };
+        assert_eq!(excluded(expired.clone(), false, false), Some(Exclusion::Expired));
+        assert_eq!(excluded(expired, false, true), None);
+    }
+}
@@ -0,0 +1,144 @@
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::page::{Page, index_path};
+
+/// One page of a paginated listing, as templates see it through `paginator`.
+#[derive(Debug, Serialize)]
+pub struct Paginator<'a> {
+    /// The pages listed on this page of the listing.
+    pub pages: &'a [Page],
+    /// Which page of the listing this is, starting at 1.
+    pub current_index: usize,
+    pub number_pagers: usize,
+    pub paginate_by: usize,
+    /// How many pages the whole listing has.
+    pub total_pages: usize,
+    /// The URL of this page of the listing relative to the site root.
+    pub path: String,
+    pub first: String,
+    pub last: String,
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+/// Splits a listing into pages of `paginate_by` entries. The first page is the
+/// listing's own page at `first_path`, the others go under `page/N/` in
+/// `directory`. A listing with nothing in it still gets one (empty) page.
+pub fn paginate<'a>(
+    config: &Config,
+    directory: &str,
+    first_path: &str,
+    pages: &'a [Page],
+    paginate_by: usize,
+) -> Vec<Paginator<'a>> {
+    let paginate_by = paginate_by.max(1);
+    let number_pagers = pages.len().div_ceil(paginate_by).max(1);
+    let path = |index: usize| {
+        if index == 1 {
+            first_path.to_string()
+        } else {
+            pager_path(config, directory, index)
+        }
+    };
+    let permalink = |index: usize| format!("{}{}", config.base_url.trim_end_matches('/'), path(index));
+
+    (1..=number_pagers)
+        .map(|index| {
+            let start = (index - 1) * paginate_by;
+            let end = (start + paginate_by).min(pages.len());
+            Paginator {
+                pages: &pages[start.min(end)..end],
+                current_index: index,
+                number_pagers,
+                paginate_by,
+                total_pages: pages.len(),
+                path: path(index),
+                first: permalink(1),
+                last: permalink(number_pagers),
+                previous: (index > 1).then(|| permalink(index - 1)),
+                next: (index < number_pagers).then(|| permalink(index + 1)),
+            }
+        })
+        .collect()
+}
+
+/// The URL of page `index` of the listing in `directory`, e.g. `/blog/page/2/`.
+pub fn pager_path(config: &Config, directory: &str, index: usize) -> String {
+    index_path(config.url_style, &format!("{}/page/{}", directory, index))
+}
+
+/// A page that sends the browser on to `url`, written to `page/1/` so that URL
+/// leads to the first page of the listing too.
+pub fn redirect_html(url: &str) -> String {
+    let url = url.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;");
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<link rel=\"canonical\" href=\"{0}\">\n\
+         <meta http-equiv=\"refresh\" content=\"0; url={0}\">\n<title>Redirecting</title>\n</head>\n\
+         <body><a href=\"{0}\">{0}</a></body>\n</html>\n",
+        url
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UrlStyle;
+    use crate::front_matter::PageMeta;
+    use std::path::Path;
+
+    fn pages(count: usize) -> Vec<Page> {
+        (0..count)
+            .map(|index| {
+                let source = format!("./content/blog/{}.md", index);
+                Page::new(&Config::default(), Path::new(&source), "./content", PageMeta::default(), "").unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_paginate() {
+        let config = Config {
+            url_style: UrlStyle::Pretty,
+            base_url: String::from("https://example.com"),
+            ..Config::default()
+        };
+        let pages = pages(5);
+
+        let pagers = paginate(&config, "blog", "/blog/", &pages, 2);
+
+        assert_eq!(pagers.len(), 3);
+        assert_eq!(pagers[0].path, "/blog/");
+        assert_eq!(pagers[0].pages.len(), 2);
+        assert_eq!(pagers[0].previous, None);
+        assert_eq!(pagers[0].next.as_deref(), Some("https://example.com/blog/page/2/"));
+
+        let last = &pagers[2];
+        assert_eq!(last.path, "/blog/page/3/");
+        assert_eq!(last.current_index, 3);
+        assert_eq!(last.number_pagers, 3);
+        assert_eq!(last.total_pages, 5);
+        assert_eq!(last.pages.len(), 1);
+        assert_eq!(last.pages[0].slug, "4");
+        assert_eq!(last.first, "https://example.com/blog/");
+        assert_eq!(last.last, "https://example.com/blog/page/3/");
+        assert_eq!(last.previous.as_deref(), Some("https://example.com/blog/page/2/"));
+        assert_eq!(last.next, None);
+    }
+
+    #[test]
+    fn test_paginate_empty_listing() {
+        let pagers = paginate(&Config::default(), "", "/index.html", &[], 10);
+
+        assert_eq!(pagers.len(), 1);
+        assert!(pagers[0].pages.is_empty());
+        assert_eq!(pagers[0].last, "/index.html");
+    }
+
+    #[test]
+    fn test_pager_path() {
+        assert_eq!(pager_path(&Config::default(), "tags/rust", 2), "/tags/rust/page/2/index.html");
+        let config = Config { url_style: UrlStyle::Pretty, ..Config::default() };
+        assert_eq!(pager_path(&config, "", 4), "/page/4/");
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use grass::{ErrorKind, Options, OutputStyle};
+use log::info;
+use walkdir::WalkDir;
+
+use crate::config::SassConfig;
+use crate::error::{BuildError, Stage};
+
+/// The stylesheets in `sass_dir` to compile: every `.scss` and `.sass` file
+/// except partials, whose names start with `_` and which are only there to be
+/// imported.
+pub fn stylesheets(sass_dir: &Path) -> Vec<PathBuf> {
+    let mut stylesheets: Vec<PathBuf> = WalkDir::new(sass_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            let is_partial = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('_'));
+            let is_sass = path.extension().is_some_and(|extension| extension == "scss" || extension == "sass");
+            is_sass && !is_partial
+        })
+        .collect();
+    stylesheets.sort();
+    stylesheets
+}
+
+/// Compiles `source` to a `.css` file with the same path in `output_dir` as it
+/// has in `sass_dir`, and returns where it went.
+pub fn compile(source: &Path, sass_dir: &Path, output_dir: &Path, config: &SassConfig) -> Result<PathBuf, BuildError> {
+    let relative = source.strip_prefix(sass_dir).unwrap_or(source);
+    let output_file = output_dir.join(relative).with_extension("css");
+
+    let style = if config.compressed { OutputStyle::Compressed } else { OutputStyle::Expanded };
+    let options = Options::default().style(style).load_path(sass_dir);
+    let css = grass::from_path(source, &options).map_err(|e| BuildError::new(Stage::Parse, source, describe(*e)))?;
+
+    write(&output_file, &css).map_err(|e| BuildError::new(Stage::Write, source, e))?;
+    info!("Compiled {} to {}", source.display(), output_file.display());
+    Ok(output_file)
+}
+
+// Puts the file and line the error is in before grass's message, which only
+// has them in the pretty-printed version.
+fn describe(error: grass::Error) -> anyhow::Error {
+    match error.kind() {
+        ErrorKind::ParseError { message, loc, .. } => {
+            anyhow!("{} on line {}, column {}: {}", loc.file.name(), loc.begin.line + 1, loc.begin.column + 1, message)
+        },
+        ErrorKind::IoError(e) => anyhow!("{}", e),
+        ErrorKind::FromUtf8Error(_) => anyhow!("a stylesheet isn't valid UTF-8"),
+        _ => anyhow!("failed to compile"),
+    }
+}
+
+fn write(path: &Path, text: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stylesheets() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("themes")).unwrap();
+        for file in ["style.scss", "_variables.scss", "themes/dark.sass", "themes/_mixins.scss", "notes.txt"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+
+        assert_eq!(stylesheets(dir.path()), vec![dir.path().join("style.scss"), dir.path().join("themes/dark.sass")]);
+        assert!(stylesheets(&dir.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_compile() {
+        let dir = tempdir().unwrap();
+        let sass = dir.path().join("sass");
+        let output = dir.path().join("output");
+        fs::create_dir_all(sass.join("themes")).unwrap();
+        fs::write(sass.join("_colors.scss"), "$accent: #c00;\n").unwrap();
+        fs::write(sass.join("themes/dark.scss"), "@import \"colors\";\n\nbody {\n  a { color: $accent; }\n}\n").unwrap();
+
+        let css_file = compile(&sass.join("themes/dark.scss"), &sass, &output, &SassConfig::default()).unwrap();
+        assert_eq!(css_file, output.join("themes/dark.css"));
+        assert_eq!(fs::read_to_string(&css_file).unwrap(), "body a {\n  color: #c00;\n}\n");
+
+        compile(&sass.join("themes/dark.scss"), &sass, &output, &SassConfig { compressed: true }).unwrap();
+        assert_eq!(fs::read_to_string(&css_file).unwrap(), "body a{color:#c00}");
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("_broken.scss"), "a {\n  color: $missing;\n}\n").unwrap();
+        fs::write(dir.path().join("style.scss"), "@import \"broken\";\n").unwrap();
+
+        let error = compile(&dir.path().join("style.scss"), dir.path(), &dir.path().join("output"), &SassConfig::default())
+            .unwrap_err();
+
+        assert_eq!(error.stage(), Stage::Parse);
+        assert_eq!(error.file(), dir.path().join("style.scss"));
+        let cause = error.cause().to_string();
+        assert!(cause.ends_with("_broken.scss on line 2, column 10: Undefined variable."), "{}", cause);
+        assert!(!dir.path().join("output/style.css").exists());
+    }
+}
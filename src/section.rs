@@ -0,0 +1,345 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::page::{Page, index_path};
+
+/// Metadata read from the front matter of a section's `_index.md`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SectionMeta {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub sort_by: SortBy,
+    /// Where the section goes among its siblings, lightest first.
+    pub weight: Option<i64>,
+    pub template: Option<String>,
+    /// Splits the index page into pages listing this many pages each.
+    pub paginate_by: Option<usize>,
+    /// Writes feeds for the pages of the section as well as for the whole site.
+    pub generate_feeds: bool,
+    pub extra: BTreeMap<String, tera::Value>,
+}
+
+/// The order a section lists its pages in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// By file name.
+    #[default]
+    None,
+    /// Newest first, with undated pages last.
+    Date,
+    Title,
+    /// Lightest first, with unweighted pages last.
+    Weight,
+}
+
+/// A directory of the content directory along with the pages and sections in it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Section {
+    #[serde(flatten)]
+    pub meta: SectionMeta,
+    /// The directory relative to the content directory, e.g. `blog`. Empty for the
+    /// content directory itself. Translated sections share it, so the German
+    /// `blog` is `blog` too.
+    pub directory: String,
+    /// The language of the section and its pages.
+    pub lang: String,
+    /// The URL of the section's index page relative to the site root, e.g. `/blog/`.
+    pub path: String,
+    pub permalink: String,
+    pub pages: Vec<Page>,
+    pub subsections: Vec<Section>,
+    /// The `_index.md` the section came from, if it has one.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+    #[serde(skip)]
+    pub markdown: String,
+    /// The line of the `_index.md` the Markdown starts on, below the front matter.
+    #[serde(skip)]
+    pub markdown_line: usize,
+}
+
+impl Section {
+    pub fn new(
+        config: &Config,
+        lang: &str,
+        directory: &str,
+        source: Option<PathBuf>,
+        mut meta: SectionMeta,
+        markdown: &str,
+    ) -> Section {
+        if meta.title.is_none() {
+            meta.title = Some(match directory.rsplit('/').next() {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => config.title_in(lang).to_string(),
+            });
+        }
+
+        let path = index_path(config.url_style, &config.language_directory(lang, directory));
+        let permalink = format!("{}{}", config.base_url.trim_end_matches('/'), path);
+
+        Section {
+            meta,
+            directory: directory.to_string(),
+            lang: lang.to_string(),
+            path,
+            permalink,
+            pages: Vec::new(),
+            subsections: Vec::new(),
+            source,
+            markdown: markdown.to_string(),
+            markdown_line: 1,
+        }
+    }
+}
+
+/// Sorts the pages into sections and nests the sections, returning the top-level
+/// ones (normally just the content directory itself).
+///
+/// Every directory with an `_index.md` is a section, and so is every directory
+/// holding pages, in it or further down, unless it holds an `index.md`: that
+/// makes it a page bundle, and its pages are listed by the section around it.
+///
+/// Every language gets sections of its own, listing only the pages in it, and
+/// the top-level ones come in the order of [`Config::languages`].
+pub fn assemble(config: &Config, explicit: Vec<Section>, pages: &[Page]) -> Vec<Section> {
+    let mut explicit_by_lang: BTreeMap<String, Vec<Section>> = BTreeMap::new();
+    for section in explicit {
+        explicit_by_lang.entry(section.lang.clone()).or_default().push(section);
+    }
+
+    let mut top_level = Vec::new();
+    for lang in config.languages() {
+        let explicit = explicit_by_lang.remove(lang).unwrap_or_default();
+        let pages: Vec<&Page> = pages.iter().filter(|page| page.lang == lang).collect();
+        top_level.extend(assemble_language(config, lang, explicit, &pages));
+    }
+    top_level
+}
+
+fn assemble_language(config: &Config, lang: &str, explicit: Vec<Section>, pages: &[&Page]) -> Vec<Section> {
+    let mut sections: BTreeMap<String, Section> =
+        explicit.into_iter().map(|section| (section.directory.clone(), section)).collect();
+
+    let bundles: HashSet<&str> = pages.iter().filter(|page| page.is_index()).map(|page| page.section.as_str()).collect();
+    for page in pages {
+        let mut directory = Some(page.section.as_str());
+        while let Some(current) = directory {
+            if !bundles.contains(current) && !sections.contains_key(current) {
+                let section = Section::new(config, lang, current, None, SectionMeta::default(), "");
+                sections.insert(current.to_string(), section);
+            }
+            directory = parent_directory(current);
+        }
+    }
+
+    for page in pages {
+        if let Some(section) = closest_section(&sections, &page.section) {
+            sections.get_mut(&section).unwrap().pages.push((*page).clone());
+        }
+    }
+
+    // A section's directory sorts after its parent's, so going backwards moves
+    // every section into its parent after its own subsections were moved into it.
+    let mut top_level = Vec::new();
+    while let Some((directory, mut section)) = sections.pop_last() {
+        sort_pages(&mut section.pages, section.meta.sort_by);
+        section.subsections.sort_by(|a, b| {
+            (a.meta.weight.is_none(), a.meta.weight, &a.directory)
+                .cmp(&(b.meta.weight.is_none(), b.meta.weight, &b.directory))
+        });
+
+        match parent_directory(&directory).and_then(|parent| closest_section(&sections, parent)) {
+            Some(parent) => sections.get_mut(&parent).unwrap().subsections.push(section),
+            None => top_level.push(section),
+        }
+    }
+    top_level.reverse();
+    top_level
+}
+
+/// Lists every section with its parent, parents before their subsections.
+pub fn flatten(sections: &[Section]) -> Vec<(&Section, Option<&Section>)> {
+    fn walk<'a>(sections: &'a [Section], parent: Option<&'a Section>, all: &mut Vec<(&'a Section, Option<&'a Section>)>) {
+        for section in sections {
+            all.push((section, parent));
+            walk(&section.subsections, Some(section), all);
+        }
+    }
+
+    let mut all = Vec::new();
+    walk(sections, None, &mut all);
+    all
+}
+
+fn sort_pages(pages: &mut [Page], sort_by: SortBy) {
+    match sort_by {
+        SortBy::None => {}
+        // `None` sorts before any date, so sorting in reverse puts undated pages last.
+        SortBy::Date => pages.sort_by_key(|page| Reverse(page.datetime)),
+        SortBy::Title => pages.sort_by_key(|page| page.meta.title.as_deref().unwrap_or_default().to_lowercase()),
+        SortBy::Weight => pages.sort_by_key(|page| (page.meta.weight.is_none(), page.meta.weight)),
+    }
+}
+
+fn parent_directory(directory: &str) -> Option<&str> {
+    if directory.is_empty() {
+        return None;
+    }
+    Some(directory.rfind('/').map(|index| &directory[..index]).unwrap_or_default())
+}
+
+// The section `directory` belongs to: its own if it is one, or the closest
+// parent directory that is.
+fn closest_section(sections: &BTreeMap<String, Section>, directory: &str) -> Option<String> {
+    let mut directory = Some(directory);
+    while let Some(current) = directory {
+        if sections.contains_key(current) {
+            return Some(current.to_string());
+        }
+        directory = parent_directory(current);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UrlStyle;
+    use crate::front_matter::PageMeta;
+    use std::path::Path;
+
+    fn page(source: &str, meta: PageMeta) -> Page {
+        Page::new(&Config::default(), Path::new(source), "./content", meta, "").unwrap()
+    }
+
+    fn directories(sections: &[Section]) -> Vec<&str> {
+        sections.iter().map(|section| section.directory.as_str()).collect()
+    }
+
+    fn titles(section: &Section) -> Vec<&str> {
+        section.pages.iter().map(|page| page.meta.title.as_deref().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_section_urls() {
+        let config = Config { base_url: String::from("https://example.com"), ..Config::default() };
+        let blog = Section::new(&config, "en", "blog", None, SectionMeta::default(), "");
+        assert_eq!(blog.path, "/blog/index.html");
+        assert_eq!(blog.permalink, "https://example.com/blog/index.html");
+        assert_eq!(blog.meta.title.as_deref(), Some("blog"));
+
+        let config = Config { url_style: UrlStyle::Pretty, title: String::from("Home"), ..Config::default() };
+        let root = Section::new(&config, "en", "", None, SectionMeta::default(), "");
+        assert_eq!(root.path, "/");
+        assert_eq!(root.meta.title.as_deref(), Some("Home"));
+    }
+
+    #[test]
+    fn test_assemble_nests_sections() {
+        let config = Config::default();
+        let pages = [
+            page("./content/about.md", PageMeta::default()),
+            page("./content/blog/first.md", PageMeta::default()),
+            page("./content/blog/2024/second.md", PageMeta::default()),
+            page("./content/blog/trip/index.md", PageMeta::default()),
+            page("./content/docs/api/intro.md", PageMeta::default()),
+        ];
+        let docs = Section::new(
+            &config,
+            "en",
+            "docs",
+            Some(PathBuf::from("./content/docs/_index.md")),
+            SectionMeta { title: Some(String::from("Docs")), weight: Some(1), ..SectionMeta::default() },
+            "",
+        );
+
+        let sections = assemble(&config, vec![docs], &pages);
+
+        assert_eq!(directories(&sections), vec![""]);
+        let root = &sections[0];
+        assert_eq!(titles(root), vec!["about"]);
+        // Weighted sections go first.
+        assert_eq!(directories(&root.subsections), vec!["docs", "blog"]);
+
+        let blog = &root.subsections[1];
+        // The page bundle is listed by the section it sits in.
+        assert_eq!(titles(blog), vec!["first", "trip"]);
+        assert_eq!(directories(&blog.subsections), vec!["blog/2024"]);
+
+        let docs = &root.subsections[0];
+        assert_eq!(docs.meta.title.as_deref(), Some("Docs"));
+        assert!(docs.pages.is_empty());
+        assert_eq!(titles(&docs.subsections[0]), vec!["intro"]);
+
+        let flat: Vec<_> = flatten(&sections)
+            .into_iter()
+            .map(|(section, parent)| (section.directory.as_str(), parent.map(|parent| parent.directory.as_str())))
+            .collect();
+        assert_eq!(
+            flat,
+            vec![
+                ("", None),
+                ("docs", Some("")),
+                ("docs/api", Some("docs")),
+                ("blog", Some("")),
+                ("blog/2024", Some("blog")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_by_language() {
+        let config = Config {
+            url_style: UrlStyle::Pretty,
+            languages: BTreeMap::from([(String::from("de"), crate::config::LanguageConfig::default())]),
+            ..Config::default()
+        };
+        let page = |source: &str| Page::new(&config, Path::new(source), "./content", PageMeta::default(), "").unwrap();
+        let pages = [
+            page("./content/blog/first.md"),
+            page("./content/blog/first.de.md"),
+            page("./content/de/blog/second.md"),
+            page("./content/trip/index.md"),
+            page("./content/trip/index.de.md"),
+        ];
+
+        let sections = assemble(&config, Vec::new(), &pages);
+
+        let roots: Vec<(&str, &str)> = sections.iter().map(|section| (section.lang.as_str(), section.path.as_str())).collect();
+        assert_eq!(roots, vec![("en", "/"), ("de", "/de/")]);
+        let german = &sections[1];
+        // Translated bundles are still bundles.
+        assert_eq!(german.pages.iter().map(|page| page.path.as_str()).collect::<Vec<_>>(), vec!["/de/trip/"]);
+        let blog = &german.subsections[0];
+        assert_eq!(blog.path, "/de/blog/");
+        assert_eq!(blog.pages.iter().map(|page| page.path.as_str()).collect::<Vec<_>>(), vec!["/de/blog/first/", "/de/blog/second/"]);
+        assert_eq!(sections[0].subsections[0].pages.len(), 1);
+    }
+
+    #[test]
+    fn test_sort_pages() {
+        let dated = |source: &str, date: Option<&str>, weight: Option<i64>| {
+            page(source, PageMeta { date: date.map(String::from), weight, ..PageMeta::default() })
+        };
+        let mut pages = vec![
+            dated("./content/b.md", Some("2024-01-01"), None),
+            dated("./content/a.md", None, Some(2)),
+            dated("./content/c.md", Some("2024-06-01"), Some(1)),
+        ];
+
+        sort_pages(&mut pages, SortBy::Date);
+        assert_eq!(pages.iter().map(|page| page.slug.as_str()).collect::<Vec<_>>(), vec!["c", "b", "a"]);
+
+        sort_pages(&mut pages, SortBy::Weight);
+        assert_eq!(pages.iter().map(|page| page.slug.as_str()).collect::<Vec<_>>(), vec!["c", "a", "b"]);
+
+        sort_pages(&mut pages, SortBy::Title);
+        assert_eq!(pages.iter().map(|page| page.slug.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,460 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+use crate::error::BuildErrors;
+
+/// The URL browsers connect to for live reload events.
+const LIVE_RELOAD_PATH: &str = "/__livereload";
+
+/// How often an idle live reload connection is pinged, so connections to closed
+/// tabs get noticed and dropped.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+  var source = new EventSource("/__livereload");
+  source.addEventListener("reload", function () { location.reload(); });
+  source.addEventListener("build-error", function (event) {
+    var report = JSON.parse(event.data);
+    var overlay = document.getElementById("rusty-ssg-error");
+    if (!overlay) {
+      overlay = document.createElement("div");
+      overlay.id = "rusty-ssg-error";
+      overlay.style.cssText = "position:fixed;inset:0;z-index:2147483647;overflow:auto;" +
+        "padding:2em;background:rgba(20,0,0,.92);color:#fdd;font:14px/1.5 monospace";
+      document.body.appendChild(overlay);
+    }
+    overlay.innerHTML = "";
+    var heading = document.createElement("h2");
+    heading.textContent = "Build failed";
+    overlay.appendChild(heading);
+    report.errors.forEach(function (error) {
+      if (error.file) {
+        var file = document.createElement("h3");
+        file.textContent = error.file + (error.stage ? " (" + error.stage + ")" : "");
+        overlay.appendChild(file);
+      }
+      var message = document.createElement("pre");
+      message.style.whiteSpace = "pre-wrap";
+      message.textContent = error.message;
+      overlay.appendChild(message);
+    });
+  });
+})();
+</script>
+"#;
+
+/// Adds the live reload script to a rendered page, just before `</body>` when
+/// there is one.
+pub fn inject_live_reload(html: &str) -> String {
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(index) => format!("{}{}{}", &html[..index], LIVE_RELOAD_SCRIPT, &html[index..]),
+        None => format!("{}{}", html, LIVE_RELOAD_SCRIPT),
+    }
+}
+
+/// Tells the browsers connected to the development server about rebuilds.
+#[derive(Default)]
+pub struct LiveReload {
+    clients: Mutex<Vec<Sender<String>>>,
+    last_error: Mutex<Option<String>>,
+}
+
+impl LiveReload {
+    /// Reloads every open page.
+    pub fn build_succeeded(&self) {
+        *self.last_error.lock().unwrap() = None;
+        self.broadcast(event("reload", "{}"));
+    }
+
+    /// Shows the error over every open page. Pages opened before the next
+    /// successful build get the error too.
+    pub fn build_failed(&self, error: &anyhow::Error) {
+        let report = event("build-error", &error_report(error));
+        *self.last_error.lock().unwrap() = Some(report.clone());
+        self.broadcast(report);
+    }
+
+    fn broadcast(&self, message: String) {
+        // Sending only fails once the client's connection has gone away.
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(message.clone()).is_ok());
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        if let Some(report) = self.last_error.lock().unwrap().clone() {
+            let _ = tx.send(report);
+        }
+        self.clients.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+fn event(name: &str, data: &str) -> String {
+    format!("event: {}\ndata: {}\n\n", name, data)
+}
+
+// Turns a failed build into the JSON the overlay script shows, with a file,
+// stage and message for each page that failed.
+fn error_report(error: &anyhow::Error) -> String {
+    let errors: Vec<tera::Value> = match error.downcast_ref::<BuildErrors>() {
+        Some(BuildErrors(errors)) => errors
+            .iter()
+            .map(|error| {
+                error_entry(
+                    &error.file().display().to_string(),
+                    &error.stage().to_string(),
+                    &format!("{:#}", error.cause()),
+                )
+            })
+            .collect(),
+        None => vec![error_entry("", "", &format!("{:#}", error))],
+    };
+
+    let mut report = tera::Map::new();
+    report.insert(String::from("errors"), tera::Value::Array(errors));
+    tera::Value::Object(report).to_string()
+}
+
+fn error_entry(file: &str, stage: &str, message: &str) -> tera::Value {
+    let mut entry = tera::Map::new();
+    entry.insert(String::from("file"), tera::Value::from(file));
+    entry.insert(String::from("stage"), tera::Value::from(stage));
+    entry.insert(String::from("message"), tera::Value::from(message));
+    tera::Value::Object(entry)
+}
+
+/// Starts serving `root` on `address` in the background.
+pub fn serve(root: PathBuf, address: &str, live_reload: Arc<LiveReload>) -> Result<()> {
+    let listener = TcpListener::bind(address).with_context(|| format!("failed to listen on {}", address))?;
+    info!("Serving {} at http://{}/", root.display(), address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let root = root.clone();
+            let live_reload = Arc::clone(&live_reload);
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &root, &live_reload) {
+                    debug!("Connection error: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, root: &Path, live_reload: &LiveReload) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // The headers don't matter to us, but they have to be read.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or("/");
+    let raw_path = target.split(['?', '#']).next().unwrap_or("/");
+    let url_path = percent_decode(raw_path);
+
+    let mut stream = stream;
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"Method not allowed", false);
+    }
+    let head_only = method == "HEAD";
+
+    if url_path == LIVE_RELOAD_PATH {
+        return stream_events(stream, live_reload);
+    }
+
+    match resolve(root, &url_path) {
+        Resolved::File(file) => {
+            let body = fs::read(&file)?;
+            respond(&mut stream, "200 OK", mime_type(&file), &body, head_only)
+        },
+        Resolved::AddSlash => {
+            // The path as it was sent, still percent-encoded, since a header
+            // can't hold spaces or anything outside ASCII.
+            let response = format!(
+                "HTTP/1.1 301 Moved Permanently\r\nLocation: {}/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                raw_path
+            );
+            stream.write_all(response.as_bytes())
+        },
+        Resolved::NotFound => {
+            warn!("404: {}", url_path);
+            let custom = root.join("404.html");
+            match fs::read(&custom) {
+                Ok(body) => respond(&mut stream, "404 Not Found", mime_type(&custom), &body, head_only),
+                Err(_) => respond(&mut stream, "404 Not Found", "text/plain; charset=utf-8", b"Not found", head_only),
+            }
+        },
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8], head_only: bool) -> io::Result<()> {
+    let headers = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(headers.as_bytes())?;
+    if !head_only {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+// Keeps the connection open as a server-sent event stream and passes on every
+// live reload event until the browser goes away.
+fn stream_events(mut stream: TcpStream, live_reload: &LiveReload) -> io::Result<()> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+    )?;
+    stream.flush()?;
+
+    let events = live_reload.subscribe();
+    loop {
+        let message = match events.recv_timeout(KEEP_ALIVE) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => String::from(": ping\n\n"),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        stream.write_all(message.as_bytes())?;
+        stream.flush()?;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Resolved {
+    File(PathBuf),
+    // A directory asked for without the trailing slash.
+    AddSlash,
+    NotFound,
+}
+
+// Maps a URL path onto a file below `root`, serving `index.html` for directories.
+fn resolve(root: &Path, url_path: &str) -> Resolved {
+    let relative = Path::new(url_path.trim_start_matches('/'));
+
+    // Never serve anything outside the output directory.
+    if relative.components().any(|component| !matches!(component, Component::Normal(_))) {
+        return Resolved::NotFound;
+    }
+
+    let path = root.join(relative);
+    if path.is_file() {
+        return Resolved::File(path);
+    }
+    if path.is_dir() {
+        if !url_path.ends_with('/') {
+            // Relative links inside the index page only work with the trailing slash.
+            return Resolved::AddSlash;
+        }
+        let index = path.join("index.html");
+        if index.is_file() {
+            return Resolved::File(index);
+        }
+    }
+    let html = path.with_extension("html");
+    if !url_path.ends_with('/') && html.is_file() {
+        return Resolved::File(html);
+    }
+
+    Resolved::NotFound
+}
+
+/// Decodes the `%20`-style escapes in a URL path, as the server and `check` see it.
+pub(crate) fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[index], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tempfile::tempdir;
+    use crate::error::{BuildError, Stage};
+
+    #[test]
+    fn test_inject_live_reload() {
+        let html = inject_live_reload("<html><body><p>Hi</p></BODY></html>");
+
+        assert!(html.starts_with("<html><body><p>Hi</p><script>"));
+        assert!(html.ends_with("</script>\n</BODY></html>"));
+        assert!(inject_live_reload("<p>Hi</p>").starts_with("<p>Hi</p><script>"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("blog/post")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("about.html"), "about").unwrap();
+        fs::write(root.join("blog/post/index.html"), "post").unwrap();
+
+        assert_eq!(resolve(root, "/"), Resolved::File(root.join("index.html")));
+        assert_eq!(resolve(root, "/about.html"), Resolved::File(root.join("about.html")));
+        assert_eq!(resolve(root, "/about"), Resolved::File(root.join("about.html")));
+        assert_eq!(resolve(root, "/blog/post/"), Resolved::File(root.join("blog/post/index.html")));
+        assert_eq!(resolve(root, "/blog/post"), Resolved::AddSlash);
+        assert_eq!(resolve(root, "/blog/"), Resolved::NotFound);
+        assert_eq!(resolve(root, "/../secret"), Resolved::NotFound);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("/hello%20world/caf%C3%A9"), "/hello world/café");
+        assert_eq!(percent_decode("/100%"), "/100%");
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(mime_type(Path::new("a/index.html")), "text/html; charset=utf-8");
+        assert_eq!(mime_type(Path::new("site.CSS")), "text/css; charset=utf-8");
+        assert_eq!(mime_type(Path::new("font.woff2")), "font/woff2");
+        assert_eq!(mime_type(Path::new("download")), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_live_reload_events() {
+        let live_reload = LiveReload::default();
+        let events = live_reload.subscribe();
+
+        live_reload.build_failed(&anyhow::anyhow!("bad \"config\""));
+        let report = events.recv().unwrap();
+        assert_eq!(
+            report,
+            "event: build-error\ndata: {\"errors\":[{\"file\":\"\",\"message\":\"bad \\\"config\\\"\",\"stage\":\"\"}]}\n\n"
+        );
+
+        // New pages see the error until the next good build.
+        let late = live_reload.subscribe();
+        assert_eq!(late.recv().unwrap(), report);
+
+        live_reload.build_succeeded();
+        assert_eq!(events.recv().unwrap(), "event: reload\ndata: {}\n\n");
+        assert!(live_reload.subscribe().try_recv().is_err());
+    }
+
+    #[test]
+    fn test_error_report_lists_failed_pages() {
+        let errors = BuildErrors(vec![BuildError::new(
+            Stage::Parse,
+            "content/a.md",
+            anyhow::anyhow!("never closed"),
+        )]);
+
+        let report = error_report(&anyhow::Error::from(errors));
+
+        assert_eq!(
+            report,
+            r#"{"errors":[{"file":"content/a.md","message":"never closed","stage":"parse"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_serve() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "home").unwrap();
+        fs::write(dir.path().join("404.html"), "missing").unwrap();
+        fs::create_dir_all(dir.path().join("my café")).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        serve(dir.path().to_path_buf(), &address, Arc::new(LiveReload::default())).unwrap();
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(&address).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let home = get("/");
+        assert!(home.starts_with("HTTP/1.1 200 OK\r\n"), "{}", home);
+        assert!(home.contains("Content-Type: text/html; charset=utf-8\r\n"), "{}", home);
+        assert!(home.ends_with("\r\n\r\nhome"), "{}", home);
+
+        let missing = get("/nope");
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", missing);
+        assert!(missing.ends_with("missing"), "{}", missing);
+
+        // Redirects keep the path percent-encoded.
+        let redirect = get("/my%20caf%C3%A9?x=1");
+        assert!(redirect.starts_with("HTTP/1.1 301 Moved Permanently\r\n"), "{}", redirect);
+        assert!(redirect.contains("\r\nLocation: /my%20caf%C3%A9/\r\n"), "{}", redirect);
+    }
+}
@@ -0,0 +1,394 @@
+use std::ops::Range;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use tera::{Context, Map, Number, Tera, Value};
+
+/// Renders the shortcodes in `markdown` with the templates in `shortcodes/`.
+///
+/// Inline shortcodes look like `{{ name(key=value) }}` and block shortcodes like
+/// `{% name(key=value) %}...{% end %}`, where the body is Markdown, rendered with
+/// `render_body` and handed to the template as `body`. Shortcodes in code are
+/// left alone.
+///
+/// Each shortcode is swapped for a placeholder, so the Markdown parser doesn't
+/// get to mangle its HTML. The HTML comes back with the placeholders, to be put
+/// in place by [`insert_shortcodes`] once the Markdown has been converted.
+/// `first_line` is the line of the file `markdown` starts on, for errors.
+pub fn render_shortcodes(
+    tera: &Tera,
+    markdown: &str,
+    first_line: usize,
+    options: Options,
+    render_body: &dyn Fn(&str, usize) -> Result<String>,
+) -> Result<(String, Vec<String>)> {
+    let code = code_ranges(markdown, options);
+    let line_of = |offset: usize| first_line + markdown[..offset].matches('\n').count();
+
+    let mut output = String::with_capacity(markdown.len());
+    let mut rendered = Vec::new();
+    let mut position = 0;
+    let mut search = 0;
+    while let Some(start) = find_tag(markdown, search, &code) {
+        let line = line_of(start);
+        let Some((tag, end)) = read_tag(markdown, start).with_context(|| format!("in the shortcode on line {}", line))?
+        else {
+            // Something like `{{ this }}` isn't a shortcode, just text.
+            search = start + 2;
+            continue;
+        };
+
+        let (html, end) = match tag {
+            ShortcodeTag::Inline(call) => {
+                let html = render(tera, &call, None).with_context(|| format!("in the shortcode on line {}", line))?;
+                (html, end)
+            },
+            ShortcodeTag::Start(call) => {
+                let (body, after) = find_end(markdown, end, &code)?
+                    .ok_or_else(|| anyhow!("`{}` is never closed with `{{% end %}}`", call.name))
+                    .with_context(|| format!("in the shortcode on line {}", line))?;
+                let body_html = render_body(&markdown[body.clone()], line_of(body.start))?;
+                let html = render(tera, &call, Some(&body_html))
+                    .with_context(|| format!("in the shortcode on line {}", line))?;
+                (html, after)
+            },
+            ShortcodeTag::End => bail!("`{{% end %}}` on line {} has no shortcode to end", line),
+        };
+
+        output.push_str(&markdown[position..start]);
+        output.push_str(&placeholder(rendered.len()));
+        // Keep the lines that follow where they were, so errors about them still
+        // point at the right line.
+        output.push_str(&"\n".repeat(markdown[start..end].matches('\n').count()));
+        rendered.push(html);
+        position = end;
+        search = end;
+    }
+    output.push_str(&markdown[position..]);
+    Ok((output, rendered))
+}
+
+/// Puts the HTML of the shortcodes in place of their placeholders. A shortcode
+/// on a line of its own replaces the paragraph the placeholder ended up in.
+pub fn insert_shortcodes(html: &str, rendered: &[String]) -> String {
+    let mut html = html.to_string();
+    for (index, shortcode) in rendered.iter().enumerate() {
+        let placeholder = placeholder(index);
+        html = html.replace(&format!("<p>{}</p>", placeholder), shortcode).replace(&placeholder, shortcode);
+    }
+    html
+}
+
+// U+FFFC stands in for an object, and the Markdown parser leaves it alone.
+fn placeholder(index: usize) -> String {
+    format!("\u{fffc}shortcode{}\u{fffc}", index)
+}
+
+// The inline code and code blocks in `markdown`.
+fn code_ranges(markdown: &str, options: Options) -> Vec<Range<usize>> {
+    Parser::new_ext(markdown, options)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Code(_) | Event::Start(Tag::CodeBlock(_)) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+// Finds the next `{{` or `{%` at or after `from` that isn't in code.
+fn find_tag(markdown: &str, from: usize, code: &[Range<usize>]) -> Option<usize> {
+    let mut from = from;
+    while let Some(index) = markdown.get(from..)?.find(['{']) {
+        let start = from + index;
+        let rest = &markdown[start..];
+        if (rest.starts_with("{{") || rest.starts_with("{%")) && !code.iter().any(|range| range.contains(&start)) {
+            return Some(start);
+        }
+        from = start + 1;
+    }
+    None
+}
+
+// Finds the `{% end %}` matching a block shortcode whose start tag ends at
+// `from`, skipping over any block shortcodes inside it. Returns the range of the
+// body and where the end tag ends.
+fn find_end(markdown: &str, from: usize, code: &[Range<usize>]) -> Result<Option<(Range<usize>, usize)>> {
+    let mut depth = 1;
+    let mut search = from;
+    while let Some(start) = find_tag(markdown, search, code) {
+        let Some((tag, end)) = read_tag(markdown, start)? else {
+            search = start + 2;
+            continue;
+        };
+        match tag {
+            ShortcodeTag::Start(_) => depth += 1,
+            ShortcodeTag::End => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some((from..start, end)));
+                }
+            },
+            ShortcodeTag::Inline(_) => {},
+        }
+        search = end;
+    }
+    Ok(None)
+}
+
+#[derive(Debug, PartialEq)]
+struct Call {
+    name: String,
+    args: Map<String, Value>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ShortcodeTag {
+    Inline(Call),
+    Start(Call),
+    End,
+}
+
+// Reads the tag starting at `start`, returning it along with where it ends, or
+// nothing when it doesn't look like a shortcode at all.
+fn read_tag(markdown: &str, start: usize) -> Result<Option<(ShortcodeTag, usize)>> {
+    let mut cursor = Cursor { text: markdown, position: start + 2 };
+    let block = markdown[start..].starts_with("{%");
+    let close = if block { "%}" } else { "}}" };
+
+    cursor.skip_whitespace();
+    let Some(name) = cursor.identifier() else {
+        return Ok(None);
+    };
+    cursor.skip_whitespace();
+    if block && name == "end" && cursor.eat(close) {
+        return Ok(Some((ShortcodeTag::End, cursor.position)));
+    }
+    if !cursor.eat("(") {
+        return Ok(None);
+    }
+
+    let mut args = Map::new();
+    loop {
+        cursor.skip_whitespace();
+        if cursor.eat(")") {
+            break;
+        }
+        let key = cursor
+            .identifier()
+            .ok_or_else(|| anyhow!("expected an argument name or `)` after `{}(`", name))?;
+        cursor.skip_whitespace();
+        if !cursor.eat("=") {
+            bail!("expected `=` after the argument `{}` of `{}`", key, name);
+        }
+        let value = cursor.value().with_context(|| format!("invalid value for the argument `{}` of `{}`", key, name))?;
+        args.insert(key.to_string(), value);
+        cursor.skip_whitespace();
+        if !cursor.eat(",") && !cursor.rest().starts_with(')') {
+            bail!("expected `,` or `)` after the argument `{}` of `{}`", key, name);
+        }
+    }
+    cursor.skip_whitespace();
+    if !cursor.eat(close) {
+        bail!("expected `{}` to close the shortcode `{}`", close, name);
+    }
+
+    let call = Call { name: name.to_string(), args };
+    let tag = if block { ShortcodeTag::Start(call) } else { ShortcodeTag::Inline(call) };
+    Ok(Some((tag, cursor.position)))
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, expected: &str) -> bool {
+        let found = self.rest().starts_with(expected);
+        if found {
+            self.position += expected.len();
+        }
+        found
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let length = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        if length == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        self.position += length;
+        Some(&rest[..length])
+    }
+
+    // A string in single or double quotes, a number, `true`, `false` or a list of
+    // those in square brackets.
+    fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let mut value = String::new();
+            let mut chars = rest.char_indices().skip(1);
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, escaped)) => value.push(escaped),
+                        None => break,
+                    },
+                    c if c == quote => {
+                        self.position += index + 1;
+                        return Ok(Value::String(value));
+                    },
+                    c => value.push(c),
+                }
+            }
+            bail!("the string is never closed");
+        }
+        if self.eat("[") {
+            let mut values = Vec::new();
+            loop {
+                self.skip_whitespace();
+                if self.eat("]") {
+                    return Ok(Value::Array(values));
+                }
+                values.push(self.value()?);
+                self.skip_whitespace();
+                if !self.eat(",") && !self.rest().starts_with(']') {
+                    bail!("expected `,` or `]` in the list");
+                }
+            }
+        }
+
+        let length = rest.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))).unwrap_or(rest.len());
+        let word = &rest[..length];
+        let value = match word {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => match word.parse::<i64>() {
+                Ok(number) => Value::from(number),
+                Err(_) => word
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(Number::from_f64)
+                    .map(Value::Number)
+                    .ok_or_else(|| anyhow!("expected a string, number, boolean or list, found `{}`", word))?,
+            },
+        };
+        self.position += length;
+        Ok(value)
+    }
+}
+
+fn render(tera: &Tera, call: &Call, body: Option<&str>) -> Result<String> {
+    let template = format!("shortcodes/{}.html", call.name);
+    if !tera.get_template_names().any(|name| name == template) {
+        bail!("unknown shortcode `{}`, there is no template {}", call.name, template);
+    }
+
+    let mut context = Context::from_value(Value::Object(call.args.clone()))?;
+    if let Some(body) = body {
+        context.insert("body", body);
+    }
+    tera.render(&template, &context)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("failed to render {}", template))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::html;
+
+    fn tera() -> Tera {
+        let mut tera = Tera::default();
+        tera.add_raw_templates([
+            ("shortcodes/youtube.html", "<iframe src=\"https://youtube.com/embed/{{ id }}\"{% if autoplay %} allow=\"autoplay\"{% endif %}></iframe>"),
+            ("shortcodes/note.html", "<div class=\"note {{ kind }}\">{{ body | safe }}</div>"),
+            ("shortcodes/sum.html", "{{ numbers | join(sep=\"+\") }}"),
+        ])
+        .unwrap();
+        tera
+    }
+
+    // Converts Markdown the way pages are converted: shortcodes, then Markdown,
+    // then the shortcodes put back.
+    fn convert(tera: &Tera, markdown: &str, first_line: usize) -> Result<String> {
+        let (markdown, rendered) =
+            render_shortcodes(tera, markdown, first_line, Options::empty(), &|body, line| convert(tera, body, line))?;
+        let mut output = String::new();
+        html::push_html(&mut output, Parser::new(&markdown));
+        Ok(insert_shortcodes(&output, &rendered))
+    }
+
+    #[test]
+    fn test_inline_shortcode() {
+        let html = convert(&tera(), "Watch this:\n\n{{ youtube(id=\"dQw4w9WgXcQ\", autoplay=true) }}\n\nNice {{ sum(numbers=[1, 2.5]) }}.", 1).unwrap();
+
+        assert_eq!(
+            html,
+            "<p>Watch this:</p>\n<iframe src=\"https://youtube.com/embed/dQw4w9WgXcQ\" allow=\"autoplay\"></iframe>\n<p>Nice 1+2.5.</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_block_shortcode() {
+        let markdown = "{% note(kind='warning') %}\nBe **careful**.\n\n{% note(kind=\"inner\") %}Nested{% end %}\n{% end %}\n\nAfter";
+
+        let html = convert(&tera(), markdown, 1).unwrap();
+
+        assert_eq!(
+            html,
+            "<div class=\"note warning\"><p>Be <strong>careful</strong>.</p>\n<div class=\"note inner\"><p>Nested</p>\n</div>\n</div>\n<p>After</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_shortcodes_in_code_are_left_alone() {
+        let html = convert(&tera(), "`{{ youtube(id=1) }}`\n\n```\n{% note() %}\n```\n\n{{ not a shortcode }}", 1).unwrap();
+
+        assert_eq!(
+            html,
+            "<p><code>{{ youtube(id=1) }}</code></p>\n<pre><code>{% note() %}\n</code></pre>\n<p>{{ not a shortcode }}</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_errors_mention_the_line() {
+        let tera = tera();
+        let error = |markdown: &str| format!("{:#}", convert(&tera, markdown, 3).unwrap_err());
+
+        assert_eq!(
+            error("Intro\n\n{{ vimeo(id=1) }}"),
+            "in the shortcode on line 5: unknown shortcode `vimeo`, there is no template shortcodes/vimeo.html"
+        );
+        assert_eq!(
+            error("\n{{ youtube(id=) }}"),
+            "in the shortcode on line 4: invalid value for the argument `id` of `youtube`: \
+             expected a string, number, boolean or list, found ``"
+        );
+        assert_eq!(
+            error("{{ youtube(id=\"x\" }}"),
+            "in the shortcode on line 3: expected `,` or `)` after the argument `id` of `youtube`"
+        );
+        assert_eq!(
+            error("{% note(kind=\"a\") %}\nNever closed"),
+            "in the shortcode on line 3: `note` is never closed with `{% end %}`"
+        );
+        assert_eq!(error("Text\n{% end %}"), "`{% end %}` on line 4 has no shortcode to end");
+        // Errors in the body point at the body's own lines.
+        assert_eq!(
+            error("{% note(kind=\"a\") %}\n\n{{ vimeo(id=1) }}\n{% end %}"),
+            "in the shortcode on line 5: unknown shortcode `vimeo`, there is no template shortcodes/vimeo.html"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::feed::escape_xml;
+
+/// The most URLs one sitemap file may list, as set by the sitemap protocol.
+pub const MAX_URLS: usize = 50_000;
+
+/// How often a page is likely to change, as a hint for search engines.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFreq {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeFreq::Always => "always",
+            ChangeFreq::Hourly => "hourly",
+            ChangeFreq::Daily => "daily",
+            ChangeFreq::Weekly => "weekly",
+            ChangeFreq::Monthly => "monthly",
+            ChangeFreq::Yearly => "yearly",
+            ChangeFreq::Never => "never",
+        }
+    }
+}
+
+/// One URL listed in the sitemap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapEntry {
+    pub permalink: String,
+    pub lastmod: Option<DateTime<FixedOffset>>,
+    pub changefreq: Option<ChangeFreq>,
+    pub priority: Option<f32>,
+    /// The page in every language it is written in, as `(language, permalink)`,
+    /// for `hreflang` links. Empty for pages that aren't translated.
+    pub alternates: Vec<(String, String)>,
+}
+
+/// Writes the sitemap for `entries`, returning each file's path relative to the
+/// site root along with its contents. Up to `max_urls` entries go in
+/// `/sitemap.xml`; past that they are split over `/sitemap1.xml`,
+/// `/sitemap2.xml` and so on, and `/sitemap.xml` becomes the index of those.
+pub fn sitemaps(base_url: &str, entries: &[SitemapEntry], max_urls: usize) -> Vec<(String, String)> {
+    if entries.len() <= max_urls {
+        return vec![(String::from("/sitemap.xml"), urlset(entries))];
+    }
+
+    let base_url = base_url.trim_end_matches('/');
+    let mut files = Vec::new();
+    let mut index = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (number, chunk) in entries.chunks(max_urls.max(1)).enumerate() {
+        let path = format!("/sitemap{}.xml", number + 1);
+        index.push_str("<sitemap>\n");
+        index.push_str(&format!("<loc>{}</loc>\n", escape_xml(&format!("{}{}", base_url, path))));
+        if let Some(lastmod) = chunk.iter().filter_map(|entry| entry.lastmod).max() {
+            index.push_str(&format!("<lastmod>{}</lastmod>\n", w3c_datetime(lastmod)));
+        }
+        index.push_str("</sitemap>\n");
+        files.push((path, urlset(chunk)));
+    }
+    index.push_str("</sitemapindex>\n");
+    files.insert(0, (String::from("/sitemap.xml"), index));
+    files
+}
+
+fn urlset(entries: &[SitemapEntry]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\"",
+    );
+    // The `hreflang` links come from the XHTML namespace.
+    if entries.iter().any(|entry| !entry.alternates.is_empty()) {
+        xml.push_str(" xmlns:xhtml=\"http://www.w3.org/1999/xhtml\"");
+    }
+    xml.push_str(">\n");
+    for entry in entries {
+        xml.push_str("<url>\n");
+        xml.push_str(&format!("<loc>{}</loc>\n", escape_xml(&entry.permalink)));
+        for (lang, permalink) in &entry.alternates {
+            xml.push_str(&format!(
+                "<xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}\"/>\n",
+                escape_xml(lang),
+                escape_xml(permalink)
+            ));
+        }
+        if let Some(lastmod) = entry.lastmod {
+            xml.push_str(&format!("<lastmod>{}</lastmod>\n", w3c_datetime(lastmod)));
+        }
+        if let Some(changefreq) = entry.changefreq {
+            xml.push_str(&format!("<changefreq>{}</changefreq>\n", changefreq.as_str()));
+        }
+        if let Some(priority) = entry.priority {
+            xml.push_str(&format!("<priority>{}</priority>\n", priority));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn w3c_datetime(datetime: DateTime<FixedOffset>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// The `robots.txt` written when the templates don't have one of their own.
+pub fn default_robots_txt(sitemap_url: &str) -> String {
+    format!("User-agent: *\nAllow: /\n\nSitemap: {}\n", sitemap_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date::parse_date;
+
+    fn entry(permalink: &str, lastmod: Option<&str>) -> SitemapEntry {
+        SitemapEntry {
+            permalink: permalink.to_string(),
+            lastmod: lastmod.map(|date| parse_date(date).unwrap()),
+            changefreq: None,
+            priority: None,
+            alternates: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let entries = [
+            SitemapEntry {
+                changefreq: Some(ChangeFreq::Weekly),
+                priority: Some(0.8),
+                ..entry("https://example.com/a?x=1&y=2", Some("2024-03-09T10:30:00+01:00"))
+            },
+            entry("https://example.com/b/", None),
+        ];
+
+        let files = sitemaps("https://example.com", &entries, MAX_URLS);
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "/sitemap.xml");
+        assert_eq!(
+            files[0].1,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n\
+             <url>\n<loc>https://example.com/a?x=1&amp;y=2</loc>\n<lastmod>2024-03-09T10:30:00+01:00</lastmod>\n\
+             <changefreq>weekly</changefreq>\n<priority>0.8</priority>\n</url>\n\
+             <url>\n<loc>https://example.com/b/</loc>\n</url>\n\
+             </urlset>\n"
+        );
+    }
+
+    #[test]
+    fn test_sitemap_alternates() {
+        let alternates = vec![
+            (String::from("en"), String::from("https://example.com/intro/")),
+            (String::from("de"), String::from("https://example.com/de/intro/")),
+        ];
+        let entries = [
+            SitemapEntry { alternates: alternates.clone(), ..entry("https://example.com/intro/", None) },
+            SitemapEntry { alternates, ..entry("https://example.com/de/intro/", None) },
+        ];
+
+        let files = sitemaps("https://example.com", &entries, MAX_URLS);
+
+        assert!(files[0].1.contains(
+            "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" xmlns:xhtml=\"http://www.w3.org/1999/xhtml\">\n"
+        ));
+        assert!(files[0].1.contains(
+            "<url>\n<loc>https://example.com/de/intro/</loc>\n\
+             <xhtml:link rel=\"alternate\" hreflang=\"en\" href=\"https://example.com/intro/\"/>\n\
+             <xhtml:link rel=\"alternate\" hreflang=\"de\" href=\"https://example.com/de/intro/\"/>\n</url>\n"
+        ), "{}", files[0].1);
+    }
+
+    #[test]
+    fn test_sitemap_index() {
+        let entries = [
+            entry("https://example.com/a", Some("2024-01-01")),
+            entry("https://example.com/b", Some("2024-02-01")),
+            entry("https://example.com/c", None),
+        ];
+
+        let files = sitemaps("https://example.com/", &entries, 2);
+
+        let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["/sitemap.xml", "/sitemap1.xml", "/sitemap2.xml"]);
+        assert_eq!(
+            files[0].1,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n\
+             <sitemap>\n<loc>https://example.com/sitemap1.xml</loc>\n<lastmod>2024-02-01T00:00:00Z</lastmod>\n</sitemap>\n\
+             <sitemap>\n<loc>https://example.com/sitemap2.xml</loc>\n</sitemap>\n\
+             </sitemapindex>\n"
+        );
+        assert!(files[1].1.contains("/a</loc>") && files[1].1.contains("/b</loc>"));
+        assert!(files[2].1.contains("/c</loc>"));
+    }
+}
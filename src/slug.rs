@@ -0,0 +1,30 @@
+/// Turns `text` into something that can go in a URL or an HTML id: lowercase
+/// letters and digits, with everything else collapsed into single dashes.
+/// Letters outside ASCII are kept, so `Café Crème` becomes `café-crème`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(slug.trim_end_matches('-').len());
+    slug
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Rust"), "rust");
+        assert_eq!(slugify("  Static Site   Generators! "), "static-site-generators");
+        assert_eq!(slugify("C++ & Rust"), "c-rust");
+        assert_eq!(slugify("Café Crème"), "café-crème");
+        assert_eq!(slugify("web_dev 2.0"), "web-dev-2-0");
+        assert_eq!(slugify("!!!"), "");
+    }
+}
@@ -0,0 +1,297 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::page::{Page, index_path};
+use crate::slug::slugify;
+
+/// A term as a page refers to it, e.g. the `rust` tag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TermLink {
+    /// The term as it was first written in a page's front matter.
+    pub name: String,
+    pub slug: String,
+    /// The URL of the term's page relative to the site root, e.g. `/tags/rust/`.
+    pub path: String,
+    pub permalink: String,
+}
+
+/// A term along with every page that has it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Term {
+    #[serde(flatten)]
+    pub link: TermLink,
+    pub pages: Vec<Page>,
+}
+
+/// A taxonomy declared in the config along with all of its terms.
+#[derive(Debug, Clone, Serialize)]
+pub struct Taxonomy {
+    pub name: String,
+    /// The language of the pages it groups.
+    pub lang: String,
+    /// The URL of the page listing the terms, e.g. `/tags/`.
+    pub path: String,
+    pub permalink: String,
+    pub terms: Vec<Term>,
+}
+
+impl Taxonomy {
+    fn new(config: &Config, lang: &str, name: &str) -> Taxonomy {
+        let path = index_path(config.url_style, &config.language_directory(lang, name));
+        Taxonomy {
+            name: name.to_string(),
+            lang: lang.to_string(),
+            permalink: permalink(config, &path),
+            path,
+            terms: Vec::new(),
+        }
+    }
+
+    pub fn summary(&self) -> TaxonomySummary<'_> {
+        TaxonomySummary {
+            name: &self.name,
+            path: &self.path,
+            permalink: &self.permalink,
+            terms: self
+                .terms
+                .iter()
+                .map(|term| TermSummary { link: &term.link, page_count: term.pages.len() })
+                .collect(),
+        }
+    }
+}
+
+/// Works out the terms a page has from its front matter. Every taxonomy named has
+/// to be declared in the config. The `tags` list counts towards the `tags`
+/// taxonomy when there is one.
+pub fn page_terms(
+    config: &Config,
+    lang: &str,
+    taxonomies: &BTreeMap<String, Vec<String>>,
+    tags: &[String],
+) -> Result<BTreeMap<String, Vec<TermLink>>> {
+    let mut terms = BTreeMap::new();
+    for (taxonomy, names) in taxonomies {
+        if config.taxonomy(taxonomy).is_none() {
+            let declared: Vec<&str> = config.taxonomies.iter().map(|taxonomy| taxonomy.name.as_str()).collect();
+            bail!(
+                "unknown taxonomy `{}`, the config declares: {}",
+                taxonomy,
+                if declared.is_empty() { String::from("none") } else { declared.join(", ") }
+            );
+        }
+        add_terms(config, lang, &mut terms, taxonomy, names)?;
+    }
+    if config.taxonomy("tags").is_some() {
+        add_terms(config, lang, &mut terms, "tags", tags)?;
+    }
+    Ok(terms)
+}
+
+fn add_terms(
+    config: &Config,
+    lang: &str,
+    terms: &mut BTreeMap<String, Vec<TermLink>>,
+    taxonomy: &str,
+    names: &[String],
+) -> Result<()> {
+    let links: &mut Vec<TermLink> = terms.entry(taxonomy.to_string()).or_default();
+    for name in names {
+        let slug = slugify(name);
+        if slug.is_empty() {
+            bail!("`{}` can't be a term of `{}`, it has no letters or digits", name, taxonomy);
+        }
+        // `Rust` and `rust` are the same term.
+        if links.iter().all(|link| link.slug != slug) {
+            let directory = config.language_directory(lang, &format!("{}/{}", taxonomy, slug));
+            let path = index_path(config.url_style, &directory);
+            links.push(TermLink { name: name.trim().to_string(), slug, permalink: permalink(config, &path), path });
+        }
+    }
+    Ok(())
+}
+
+/// Groups the pages in the language `lang` by the terms they have, for every
+/// taxonomy in the config. Terms are sorted by slug and their pages newest first.
+pub fn collect(config: &Config, lang: &str, pages: &[Page]) -> Vec<Taxonomy> {
+    config
+        .taxonomies
+        .iter()
+        .map(|declared| {
+            let mut terms: BTreeMap<&str, Term> = BTreeMap::new();
+            for page in pages.iter().filter(|page| page.lang == lang) {
+                for link in page.taxonomies.get(&declared.name).into_iter().flatten() {
+                    terms
+                        .entry(&link.slug)
+                        .or_insert_with(|| Term { link: link.clone(), pages: Vec::new() })
+                        .pages
+                        .push(page.clone());
+                }
+            }
+
+            let mut taxonomy = Taxonomy::new(config, lang, &declared.name);
+            taxonomy.terms = terms.into_values().collect();
+            for term in &mut taxonomy.terms {
+                // `None` sorts before any date, so sorting in reverse puts undated pages last.
+                term.pages.sort_by_key(|page| Reverse(page.datetime));
+            }
+            taxonomy
+        })
+        .collect()
+}
+
+/// What templates see of a taxonomy through the `taxonomies` variable: its terms,
+/// with how many pages each has rather than the pages themselves.
+#[derive(Debug, Serialize)]
+pub struct TaxonomySummary<'a> {
+    pub name: &'a str,
+    pub path: &'a str,
+    pub permalink: &'a str,
+    pub terms: Vec<TermSummary<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TermSummary<'a> {
+    #[serde(flatten)]
+    pub link: &'a TermLink,
+    pub page_count: usize,
+}
+
+/// The `taxonomies` template variable of pages in the language `lang`, keyed by
+/// taxonomy name.
+pub fn summaries<'a>(taxonomies: &'a [Taxonomy], lang: &str) -> BTreeMap<&'a str, TaxonomySummary<'a>> {
+    taxonomies
+        .iter()
+        .filter(|taxonomy| taxonomy.lang == lang)
+        .map(|taxonomy| (taxonomy.name.as_str(), taxonomy.summary()))
+        .collect()
+}
+
+fn permalink(config: &Config, path: &str) -> String {
+    format!("{}{}", config.base_url.trim_end_matches('/'), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TaxonomyConfig, UrlStyle};
+    use crate::front_matter::PageMeta;
+    use std::path::Path;
+
+    fn config() -> Config {
+        Config {
+            url_style: UrlStyle::Pretty,
+            base_url: String::from("https://example.com"),
+            taxonomies: vec![
+                TaxonomyConfig { name: String::from("tags"), ..TaxonomyConfig::default() },
+                TaxonomyConfig { name: String::from("categories"), ..TaxonomyConfig::default() },
+            ],
+            ..Config::default()
+        }
+    }
+
+    fn page(config: &Config, source: &str, date: &str, tags: &[&str], categories: &[&str]) -> Page {
+        let meta = PageMeta {
+            date: Some(date.to_string()),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            taxonomies: BTreeMap::from([(
+                String::from("categories"),
+                categories.iter().map(|category| category.to_string()).collect(),
+            )]),
+            ..PageMeta::default()
+        };
+        Page::new(config, Path::new(source), "./content", meta, "").unwrap()
+    }
+
+    #[test]
+    fn test_page_terms() {
+        let config = config();
+        let taxonomies = BTreeMap::from([(String::from("tags"), vec![String::from("Web Dev"), String::from("rust")])]);
+
+        let terms = page_terms(&config, "en", &taxonomies, &[String::from("Rust")]).unwrap();
+
+        assert_eq!(
+            terms["tags"],
+            vec![
+                TermLink {
+                    name: String::from("Web Dev"),
+                    slug: String::from("web-dev"),
+                    path: String::from("/tags/web-dev/"),
+                    permalink: String::from("https://example.com/tags/web-dev/"),
+                },
+                TermLink {
+                    name: String::from("rust"),
+                    slug: String::from("rust"),
+                    path: String::from("/tags/rust/"),
+                    permalink: String::from("https://example.com/tags/rust/"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_page_terms_errors() {
+        let config = config();
+
+        let taxonomies = BTreeMap::from([(String::from("authors"), vec![String::from("me")])]);
+        let error = page_terms(&config, "en", &taxonomies, &[]).unwrap_err();
+        assert_eq!(error.to_string(), "unknown taxonomy `authors`, the config declares: tags, categories");
+
+        let taxonomies = BTreeMap::from([(String::from("tags"), vec![String::from("???")])]);
+        assert!(page_terms(&config, "en", &taxonomies, &[]).is_err());
+
+        // Without a `tags` taxonomy, `tags` stays a plain list.
+        let terms = page_terms(&Config::default(), "en", &BTreeMap::new(), &[String::from("rust")]).unwrap();
+        assert!(terms.is_empty());
+    }
+
+    #[test]
+    fn test_collect() {
+        let config = config();
+        let pages = [
+            page(&config, "./content/old.md", "2023-01-01", &["rust"], &["Programming"]),
+            page(&config, "./content/new.md", "2024-01-01", &["Rust", "web"], &[]),
+        ];
+
+        let taxonomies = collect(&config, "en", &pages);
+
+        assert_eq!(taxonomies.len(), 2);
+        let tags = &taxonomies[0];
+        assert_eq!(tags.path, "/tags/");
+        let terms: Vec<(&str, Vec<&str>)> = tags
+            .terms
+            .iter()
+            .map(|term| (term.link.slug.as_str(), term.pages.iter().map(|page| page.slug.as_str()).collect()))
+            .collect();
+        assert_eq!(terms, vec![("rust", vec!["new", "old"]), ("web", vec!["new"])]);
+        assert_eq!(taxonomies[1].terms[0].link.name, "Programming");
+
+        let summaries = summaries(&taxonomies, "en");
+        assert_eq!(summaries["tags"].terms[0].page_count, 2);
+        assert_eq!(summaries["categories"].terms[0].link.path, "/categories/programming/");
+    }
+
+    #[test]
+    fn test_collect_by_language() {
+        let config = Config {
+            languages: BTreeMap::from([(String::from("de"), crate::config::LanguageConfig::default())]),
+            ..config()
+        };
+        let pages = [
+            page(&config, "./content/post.md", "2024-01-01", &["rust"], &[]),
+            page(&config, "./content/post.de.md", "2024-01-01", &["rust"], &[]),
+        ];
+
+        let taxonomies = collect(&config, "de", &pages);
+
+        assert_eq!(taxonomies[0].path, "/de/tags/");
+        assert_eq!(taxonomies[0].terms[0].link.path, "/de/tags/rust/");
+        assert_eq!(taxonomies[0].terms[0].pages[0].path, "/de/post/");
+        assert_eq!(collect(&config, "en", &pages)[0].terms[0].pages.len(), 1);
+        assert!(summaries(&taxonomies, "en").is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, bail};
+use tera::Tera;
+
+/// Works out the templates to try for a page, most specific first.
+///
+/// A page that names its template in its front matter looks for it in its own
+/// section and then in each parent section, so `template = "post.html"` in
+/// `blog/2024` tries `blog/2024/post.html`, `blog/post.html` and `post.html`.
+/// Other pages look for a `page.html` the same way and end up at `base_template`.
+pub fn template_candidates(section: &str, template: Option<&str>, base_template: &str) -> Vec<String> {
+    let mut candidates = section_candidates(section, template.unwrap_or("page.html"));
+    if template.is_none() {
+        candidates.push(base_template.to_string());
+    }
+    candidates
+}
+
+/// Picks the template for a page, see [`template_candidates`].
+pub fn find_template(tera: &Tera, section: &str, template: Option<&str>, base_template: &str) -> Result<String> {
+    first_existing(tera, template_candidates(section, template, base_template), template)
+}
+
+/// Picks the template for a section's index page. Sections look for their
+/// template the same way pages do, with `section.html` in place of `page.html`
+/// and no fallback to the base template.
+pub fn find_section_template(tera: &Tera, section: &str, template: Option<&str>) -> Result<String> {
+    first_existing(tera, section_candidates(section, template.unwrap_or("section.html")), template)
+}
+
+/// Picks the template for one of a taxonomy's pages: `kind` is `list` for the page
+/// listing its terms and `single` for the page of one term. Either can be given
+/// for a single taxonomy, e.g. `tags/list.html`, or for all of them, e.g.
+/// `taxonomy_list.html`.
+pub fn find_taxonomy_template(tera: &Tera, taxonomy: &str, kind: &str) -> Result<String> {
+    let candidates = vec![format!("{}/{}.html", taxonomy, kind), format!("taxonomy_{}.html", kind)];
+    first_existing(tera, candidates, None)
+}
+
+// `name` in `section`, then in each of its parents, then at the top.
+fn section_candidates(section: &str, name: &str) -> Vec<String> {
+    let mut candidates = Vec::new();
+    let mut section = section.trim_matches('/');
+    while !section.is_empty() {
+        candidates.push(format!("{}/{}", section, name));
+        section = section.rfind('/').map(|index| &section[..index]).unwrap_or_default();
+    }
+    candidates.push(name.to_string());
+    candidates
+}
+
+fn first_existing(tera: &Tera, candidates: Vec<String>, template: Option<&str>) -> Result<String> {
+    let names: HashSet<&str> = tera.get_template_names().collect();
+
+    match candidates.iter().find(|candidate| names.contains(candidate.as_str())) {
+        Some(found) => Ok(found.clone()),
+        None => match template {
+            Some(template) => bail!("template `{}` not found, tried: {}", template, candidates.join(", ")),
+            None => bail!("no template found, tried: {}", candidates.join(", ")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tera(names: &[&str]) -> Tera {
+        let mut tera = Tera::default();
+        tera.add_raw_templates(names.iter().map(|name| (*name, *name))).unwrap();
+        tera
+    }
+
+    #[test]
+    fn test_template_candidates() {
+        assert_eq!(
+            template_candidates("blog/2024", None, "base.html"),
+            vec!["blog/2024/page.html", "blog/page.html", "page.html", "base.html"]
+        );
+        assert_eq!(template_candidates("", None, "base.html"), vec!["page.html", "base.html"]);
+        assert_eq!(
+            template_candidates("blog", Some("post.html"), "base.html"),
+            vec!["blog/post.html", "post.html"]
+        );
+    }
+
+    #[test]
+    fn test_find_template_falls_back() {
+        let tera = tera(&["base.html", "page.html", "blog/page.html"]);
+
+        assert_eq!(find_template(&tera, "blog/2024", None, "base.html").unwrap(), "blog/page.html");
+        assert_eq!(find_template(&tera, "docs", None, "base.html").unwrap(), "page.html");
+
+        let tera = self::tera(&["base.html"]);
+        assert_eq!(find_template(&tera, "docs", None, "base.html").unwrap(), "base.html");
+    }
+
+    #[test]
+    fn test_find_named_template() {
+        let tera = tera(&["base.html", "post.html", "blog/post.html"]);
+
+        assert_eq!(find_template(&tera, "blog", Some("post.html"), "base.html").unwrap(), "blog/post.html");
+        assert_eq!(find_template(&tera, "docs", Some("post.html"), "base.html").unwrap(), "post.html");
+
+        let error = find_template(&tera, "docs", Some("talk.html"), "base.html").unwrap_err();
+        assert_eq!(error.to_string(), "template `talk.html` not found, tried: docs/talk.html, talk.html");
+    }
+
+    #[test]
+    fn test_find_section_template() {
+        let tera = tera(&["base.html", "section.html", "docs/section.html"]);
+
+        assert_eq!(find_section_template(&tera, "docs/api", None).unwrap(), "docs/section.html");
+        assert_eq!(find_section_template(&tera, "", None).unwrap(), "section.html");
+
+        let error = find_section_template(&self::tera(&["base.html"]), "blog", None).unwrap_err();
+        assert_eq!(error.to_string(), "no template found, tried: blog/section.html, section.html");
+    }
+
+    #[test]
+    fn test_find_taxonomy_template() {
+        let tera = tera(&["tags/single.html", "taxonomy_single.html", "taxonomy_list.html"]);
+
+        assert_eq!(find_taxonomy_template(&tera, "tags", "single").unwrap(), "tags/single.html");
+        assert_eq!(find_taxonomy_template(&tera, "tags", "list").unwrap(), "taxonomy_list.html");
+        assert_eq!(find_taxonomy_template(&tera, "categories", "single").unwrap(), "taxonomy_single.html");
+
+        let error = find_taxonomy_template(&Tera::default(), "tags", "list").unwrap_err();
+        assert_eq!(error.to_string(), "no template found, tried: tags/list.html, taxonomy_list.html");
+    }
+
+    #[test]
+    fn test_find_template_nothing_found() {
+        let error = find_template(&Tera::default(), "blog", None, "base.html").unwrap_err();
+
+        assert_eq!(error.to_string(), "no template found, tried: blog/page.html, page.html, base.html");
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long the file system has to stay quiet before a rebuild starts. Editors
+/// and `git checkout` tend to touch several files at once; this turns that burst
+/// into a single rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches `paths` and calls `on_change` with the files that changed, once per
+/// burst of changes. Directories are watched recursively. Paths that don't
+/// exist yet are picked up once they're created. Only returns if the watcher
+/// itself fails.
+pub fn watch(paths: &[PathBuf], mut on_change: impl FnMut(&[PathBuf])) -> Result<()> {
+    let mut watch = Watch::new(paths)?;
+    loop {
+        let changed = watch.next()?;
+        if !changed.is_empty() {
+            on_change(&changed);
+        }
+    }
+}
+
+struct Watch {
+    watcher: RecommendedWatcher,
+    rx: Receiver<notify::Result<Event>>,
+    paths: Vec<PathBuf>,
+    // Paths that didn't exist when last looked at. Their closest existing
+    // parent is watched instead, so we hear when they're created.
+    missing: Vec<PathBuf>,
+}
+
+impl Watch {
+    fn new(paths: &[PathBuf]) -> Result<Watch> {
+        let (tx, rx) = mpsc::channel();
+        let watcher = notify::recommended_watcher(tx).context("failed to start the file watcher")?;
+        // Events come with absolute paths, so the paths they're matched against are too.
+        let paths: Vec<PathBuf> = paths.iter().map(|path| std::path::absolute(path).unwrap_or(path.clone())).collect();
+        let mut watch = Watch { watcher, rx, missing: paths.clone(), paths };
+        watch.add_created()?;
+        Ok(watch)
+    }
+
+    // The changes to the watched paths in the next burst.
+    fn next(&mut self) -> Result<Vec<PathBuf>> {
+        let mut changed = next_changes(&self.rx)?;
+        self.add_created()?;
+        changed.retain(|path| self.paths.iter().any(|watched| path.starts_with(watched)));
+        Ok(changed)
+    }
+
+    // Starts watching the missing paths that exist now.
+    fn add_created(&mut self) -> Result<()> {
+        for path in std::mem::take(&mut self.missing) {
+            if !path.exists() {
+                let parent = path.ancestors().skip(1).find(|ancestor| ancestor.is_dir());
+                if let Some(parent) = parent {
+                    // Watching the same directory twice is fine, a watch is only
+                    // replaced.
+                    self.watcher
+                        .watch(parent, RecursiveMode::NonRecursive)
+                        .with_context(|| format!("failed to watch {}", parent.display()))?;
+                }
+                self.missing.push(path);
+                continue;
+            }
+            // Config files get replaced rather than rewritten by a lot of editors, which
+            // a watch on the file itself doesn't survive, so watch their directory.
+            let (target, mode) = if path.is_dir() {
+                (path.as_path(), RecursiveMode::Recursive)
+            } else {
+                let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+                (parent.unwrap_or(Path::new(".")), RecursiveMode::NonRecursive)
+            };
+            self.watcher
+                .watch(target, mode)
+                .with_context(|| format!("failed to watch {}", target.display()))?;
+            info!("Watching {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+// Blocks until something changes, then keeps collecting changes until things
+// have been quiet for `DEBOUNCE`.
+fn next_changes(rx: &Receiver<notify::Result<Event>>) -> Result<Vec<PathBuf>> {
+    let mut changed = BTreeSet::new();
+
+    let first = rx.recv().context("the file watcher stopped")?;
+    add_changes(&mut changed, first);
+    while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+        add_changes(&mut changed, event);
+    }
+
+    Ok(changed.into_iter().collect())
+}
+
+fn add_changes(changed: &mut BTreeSet<PathBuf>, event: notify::Result<Event>) {
+    match event {
+        // Reading a file doesn't change it.
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+        Ok(event) => changed.extend(event.paths),
+        Err(e) => warn!("File watcher error: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, ModifyKind};
+
+    fn event(kind: EventKind, path: &str) -> notify::Result<Event> {
+        Ok(Event::new(kind).add_path(PathBuf::from(path)))
+    }
+
+    #[test]
+    fn test_next_changes_debounces_a_burst() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(event(EventKind::Modify(ModifyKind::Any), "content/a.md")).unwrap();
+        tx.send(event(EventKind::Modify(ModifyKind::Any), "content/b.md")).unwrap();
+        tx.send(event(EventKind::Modify(ModifyKind::Any), "content/a.md")).unwrap();
+        tx.send(event(EventKind::Access(AccessKind::Any), "content/c.md")).unwrap();
+
+        let changed = next_changes(&rx).unwrap();
+
+        assert_eq!(changed, vec![PathBuf::from("content/a.md"), PathBuf::from("content/b.md")]);
+    }
+
+    #[test]
+    fn test_watch_picks_up_created_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let static_dir = dir.path().join("static");
+        let (tx, rx) = mpsc::channel();
+        let paths = vec![static_dir.clone()];
+        std::thread::spawn(move || {
+            let mut watch = Watch::new(&paths).unwrap();
+            tx.send(Vec::new()).unwrap();
+            while let Ok(changed) = watch.next() {
+                if !changed.is_empty() && tx.send(changed).is_err() {
+                    break;
+                }
+            }
+        });
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // Files next to the missing directory aren't reported.
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        std::fs::create_dir(&static_dir).unwrap();
+        let changed = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(changed, vec![std::path::absolute(&static_dir).unwrap()]);
+
+        // Once it's there, what happens inside it is.
+        std::fs::write(static_dir.join("site.css"), "css").unwrap();
+        let changed = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(changed.contains(&std::path::absolute(static_dir.join("site.css")).unwrap()), "{:?}", changed);
+    }
+
+    #[test]
+    fn test_next_changes_fails_when_watcher_goes_away() {
+        let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+        drop(tx);
+
+        assert!(next_changes(&rx).is_err());
+    }
+}